@@ -7,7 +7,43 @@ pub struct LLMCouncilRequestSchema {
     pub title: String,
     pub prompt: String,
     pub max_tokens: usize,
-    pub flow_control: u8,
+    // deprecated, use stages (1 -> answer, 2 -> rank, 4 -> synthesize, a stage
+    // not set in the bitmask is reused)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub flow_control: Option<u8>,
+    // the pipeline (from the config) to execute, defaults to "default"
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub pipeline: Option<String>,
+    // run, reuse or skip per stage, stages not set are run
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub stages: BTreeMap<PipelineStage, StageMode>,
+    #[serde(default)]
+    pub run_async: bool,
+    #[serde(default)]
+    pub stream: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub aggregation: Option<AggregationStrategy>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub self_vote: Option<SelfVoteMode>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub chairman_strategy: Option<ChairmanStrategy>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub seed: Option<u64>,
+    // follow-up requests with the same id receive the earlier turns as history
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub conversation_id: Option<String>,
+    // messages sent before the prompt (e.g. the system message and earlier turns of an
+    // openai request), system messages come first, the other turns after the history
+    // of the conversation
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub messages: Vec<ChatMessage>,
+    // sent to every member, override the member defaults (generation in the config)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub temperature: Option<f64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub top_p: Option<f64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub stop: Option<Vec<String>>,
 }
 
 #[allow(unused)]
@@ -23,14 +59,95 @@ pub struct ResponseObject {
     pub contents: String,
     pub process_name: String,
     pub status_code: u16,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub reply: Option<MemberReply>,
+}
+
+// the parsed chat completion of a council member
+#[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct MemberReply {
+    pub text: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub finish_reason: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub usage: Option<ChatUsage>,
+}
+
+// the chat completion returned by a council member, openai compatible servers differ
+// in the fields they send, so only the choices are required
+#[derive(Default, Debug, Clone, PartialEq, Deserialize)]
+pub struct MemberCompletion {
+    #[serde(default)]
+    pub id: String,
+    #[serde(default)]
+    pub object: String,
+    #[serde(default)]
+    pub created: u64,
+    #[serde(default)]
+    pub model: String,
+    pub choices: Vec<MemberChoice>,
+    #[serde(default)]
+    pub usage: Option<ChatUsage>,
+}
+
+#[derive(Default, Debug, Clone, PartialEq, Deserialize)]
+pub struct MemberChoice {
+    #[serde(default)]
+    pub index: usize,
+    #[serde(default)]
+    pub message: Option<MemberMessage>,
+    #[serde(default)]
+    pub finish_reason: Option<String>,
+}
+
+// the content is null when the reply has no text (e.g. a refusal or a tool call)
+#[derive(Default, Debug, Clone, PartialEq, Deserialize)]
+pub struct MemberMessage {
+    #[serde(default)]
+    pub role: String,
+    #[serde(default)]
+    pub content: Option<String>,
+}
+
+// the body posted to the document-service /write endpoint
+#[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct DocumentWriteRequest {
+    pub document: String,
+    pub content: String,
 }
 
 #[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
 pub struct ResponseSummary {
     pub documents: Vec<Document>,
-    pub summary_result: BTreeMap<String, usize>,
+    pub summary_result: AggregateResult,
     pub response_mapping: BTreeMap<String, String>,
+    #[serde(default)]
+    pub ranking_validation: BTreeMap<String, RankingParse>,
+    // the labels each judge was shown, judge -> (label -> member)
+    #[serde(default)]
+    pub judge_label_mapping: BTreeMap<String, BTreeMap<String, String>>,
+    #[serde(default)]
+    pub seed: u64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub conversation_id: Option<String>,
+    // version of the prompt templates used for the run
+    #[serde(default)]
+    pub prompt_version: String,
+    // the pipeline executed and the mode of each of its stages
+    #[serde(default)]
+    pub pipeline: String,
+    #[serde(default)]
+    pub stages: Vec<StageExecution>,
+    // members that failed a stage, stage -> (member -> reason)
+    #[serde(default)]
+    pub failed_members: BTreeMap<String, BTreeMap<String, String>>,
+    #[serde(default)]
+    pub usage: BTreeMap<String, ChatUsage>,
+    // the chairman that synthesized the answers, not set when the synthesis is skipped
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub chairman: Option<ChairmanSelection>,
 }
 
 #[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
@@ -39,3 +156,344 @@ pub struct Document {
     pub name: String,
     pub url: String,
 }
+
+#[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum RunState {
+    #[default]
+    Queued,
+    Running,
+    Completed,
+    Failed,
+}
+
+#[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RunStatus {
+    pub id: String,
+    pub title: String,
+    pub state: RunState,
+    pub stage: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub queue_position: Option<usize>,
+    pub members: BTreeMap<String, String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub summary: Option<ResponseSummary>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+#[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RunCreated {
+    pub id: String,
+    pub status_url: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub queue_position: Option<usize>,
+}
+
+#[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ProgressEvent {
+    pub event: String,
+    pub run_id: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub member: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub status: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub data: Option<serde_json::Value>,
+}
+
+impl ProgressEvent {
+    pub fn new(event: &str, run_id: &str) -> Self {
+        ProgressEvent {
+            event: event.to_string(),
+            run_id: run_id.to_string(),
+            ..Default::default()
+        }
+    }
+
+    pub fn with_member(mut self, member: &str, status: String) -> Self {
+        self.member = Some(member.to_string());
+        self.status = Some(status);
+        self
+    }
+
+    pub fn with_data(mut self, data: serde_json::Value) -> Self {
+        self.data = Some(data);
+        self
+    }
+
+    // formats the event as a server-sent event frame
+    pub fn to_sse(&self) -> String {
+        let json = serde_json::to_string(self).unwrap_or_default();
+        format!("event: {}\ndata: {}\n\n", self.event, json)
+    }
+}
+
+// openai compatible chat completion types
+
+// the request sent to each council member (and the chairman)
+#[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ChatRequest {
+    pub model: String,
+    pub messages: Vec<ChatMessage>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_tokens: Option<usize>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub temperature: Option<f64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub top_p: Option<f64>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub stop: Vec<String>,
+}
+
+impl ChatRequest {
+    // the earlier turns of a conversation followed by the new user message
+    pub fn with_history(
+        model: &str,
+        history: &[ChatMessage],
+        content: String,
+        max_tokens: Option<usize>,
+    ) -> Self {
+        let mut messages = history.to_vec();
+        messages.push(ChatMessage {
+            role: "user".to_string(),
+            content,
+        });
+        ChatRequest {
+            model: model.to_string(),
+            messages,
+            max_tokens,
+            ..Default::default()
+        }
+    }
+}
+
+#[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ChatMessage {
+    pub role: String,
+    pub content: String,
+}
+
+#[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ChatCompletionRequest {
+    pub model: String,
+    pub messages: Vec<ChatMessage>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_tokens: Option<usize>,
+    #[serde(default)]
+    pub stream: bool,
+    // council extensions, optional for openai clients
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub title: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub flow_control: Option<u8>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub pipeline: Option<String>,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub stages: BTreeMap<PipelineStage, StageMode>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub conversation_id: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub aggregation: Option<AggregationStrategy>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub self_vote: Option<SelfVoteMode>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub chairman_strategy: Option<ChairmanStrategy>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub seed: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub temperature: Option<f64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub top_p: Option<f64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub stop: Option<Vec<String>>,
+}
+
+#[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ChatCompletionResponse {
+    pub id: String,
+    pub object: String,
+    pub created: u64,
+    pub model: String,
+    pub choices: Vec<ChatChoice>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub usage: Option<ChatUsage>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub council: Option<ResponseSummary>,
+}
+
+#[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ChatChoice {
+    pub index: usize,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub message: Option<ChatMessage>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub delta: Option<ChatMessage>,
+    #[serde(default)]
+    pub finish_reason: Option<String>,
+}
+
+#[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ChatUsage {
+    #[serde(default)]
+    pub prompt_tokens: usize,
+    #[serde(default)]
+    pub completion_tokens: usize,
+    #[serde(default)]
+    pub total_tokens: usize,
+}
+
+#[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum RankingParseStatus {
+    #[default]
+    Valid,
+    Partial,
+    Invalid,
+}
+
+// the result of parsing the FINAL RANKING section of a judge
+#[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RankingParse {
+    pub status: RankingParseStatus,
+    pub ranking: Vec<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub reason: Option<String>,
+}
+
+#[derive(Default, Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum AggregationStrategy {
+    #[default]
+    Borda,
+    MeanRank,
+    Copeland,
+    Kemeny,
+}
+
+// how a judge's vote for its own response is handled
+// - include -> the judge ranks all responses (own included) and all votes count
+// - discard -> the judge ranks all responses, its own position is discarded before aggregation
+// - omit    -> the judge's own response is left out of its ranking prompt
+#[derive(Default, Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SelfVoteMode {
+    #[default]
+    Include,
+    Discard,
+    Omit,
+}
+
+// the labels each judge was shown, stored with the ranking documents so a reused
+// ranking is translated back with the labels of the run that produced it
+#[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RankingLabels {
+    pub seed: u64,
+    pub judge_labels: BTreeMap<String, BTreeMap<String, String>>,
+}
+
+// how the chairman of a run is selected
+// - fixed       -> the configured council_chairman (default)
+// - top-ranked  -> the member whose answer won the aggregated ranking
+// - round-robin -> the members that answered take turns across runs
+// - external    -> the configured council_chairman, which must not be a council member
+#[derive(Default, Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum ChairmanStrategy {
+    #[default]
+    Fixed,
+    TopRanked,
+    RoundRobin,
+    External,
+}
+
+// the chairman selected for a run and why
+#[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ChairmanSelection {
+    pub name: String,
+    pub model: String,
+    pub strategy: ChairmanStrategy,
+    pub reason: String,
+}
+
+// the aggregated rankings, scores are per candidate and the pairwise matrix holds
+// the number of judges that ranked the row candidate above the column candidate
+#[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AggregateResult {
+    pub strategy: AggregationStrategy,
+    #[serde(default)]
+    pub self_vote: SelfVoteMode,
+    pub ranking: Vec<String>,
+    pub scores: BTreeMap<String, f64>,
+    pub higher_is_better: bool,
+    pub ballots: BTreeMap<String, Vec<String>>,
+    pub pairwise: BTreeMap<String, BTreeMap<String, f64>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub kemeny_score: Option<f64>,
+    // judge -> vote weight, only set when a judge's vote is weighted
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub weights: BTreeMap<String, f64>,
+}
+
+#[derive(Default, Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum BreakerState {
+    #[default]
+    Closed,
+    Open,
+    HalfOpen,
+}
+
+// circuit breaker state of a council member, reported by the health endpoint
+#[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct BreakerStatus {
+    pub state: BreakerState,
+    pub consecutive_failures: u32,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub last_error: Option<String>,
+    // seconds until an open breaker half-opens
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub retry_in_secs: Option<u64>,
+}
+
+// the stages a pipeline is built from, the order is set per pipeline in the config
+//
+// - answer     -> every member answers the prompt (initial documents)
+// - revise     -> every member revises its answer after reading its peers' answers,
+//                 for the configured number of rounds (revision-<round> documents)
+// - rank       -> every member ranks the anonymized answers (ranking documents)
+// - synthesize -> the chairman synthesizes the answers and rankings
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum PipelineStage {
+    Answer,
+    Revise,
+    Rank,
+    Synthesize,
+}
+
+// run      -> execute the stage
+// reuse    -> read the documents stored by an earlier run with the same title
+// skip     -> do not execute the stage, later stages must not depend on it
+#[derive(Default, Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum StageMode {
+    #[default]
+    Run,
+    Reuse,
+    Skip,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct StageExecution {
+    pub stage: PipelineStage,
+    pub mode: StageMode,
+}
@@ -1,5 +1,11 @@
+use crate::api::schema::{AggregationStrategy, ChairmanStrategy, PipelineStage, SelfVoteMode};
+use crate::config::overrides::{EnvOverrides, apply_env_overrides};
+use crate::config::validate::validate_parameters;
+use custom_logger as log;
 use serde_derive::{Deserialize, Serialize};
+use std::collections::BTreeMap;
 use std::fs::File;
+use std::time::Duration;
 
 #[derive(Serialize, Deserialize, Clone, Debug)]
 pub struct Parameters {
@@ -8,17 +14,318 @@ pub struct Parameters {
     pub log_level: String,
     pub council_members: Vec<ModelSchema>,
     pub council_chairman: ModelSchema,
+    // only used by the remote document store
+    #[serde(default)]
     pub document_service_url: String,
+    #[serde(default)]
+    pub document_store: DocumentStoreSettings,
+    #[serde(default)]
+    pub documents: DocumentSettings,
+    #[serde(default)]
+    pub prompts: PromptSettings,
+    // named pipelines, the ordered stages to execute
+    #[serde(default = "default_pipelines")]
+    pub pipelines: BTreeMap<String, Vec<PipelineStage>>,
     pub server_port: usize,
-    pub semaphore: bool,
+    #[serde(default = "default_max_concurrent_runs")]
+    pub max_concurrent_runs: usize,
+    #[serde(default = "default_max_queued_runs")]
+    pub max_queued_runs: usize,
+    #[serde(default)]
+    pub aggregation_strategy: AggregationStrategy,
+    #[serde(default)]
+    pub self_vote: SelfVoteMode,
+    #[serde(default)]
+    pub chairman_strategy: ChairmanStrategy,
+    #[serde(default)]
+    pub quorum: QuorumPolicy,
+    // number of earlier turns (question and synthesis) replayed in a conversation
+    #[serde(default = "default_history_window")]
+    pub history_window: usize,
+    #[serde(default)]
+    pub revision: RevisionSettings,
+    #[serde(default)]
+    pub http: HttpSettings,
+    #[serde(default)]
+    pub breaker: BreakerPolicy,
+    // bearer token of the admin api, the admin api is disabled when not set
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub admin_token: Option<String>,
 }
 
-#[derive(Serialize, Deserialize, Clone, Debug, Ord, PartialOrd, Eq, PartialEq)]
+// minimum number of members that must answer a stage for the run to continue, when
+// not set the initial quorum is 2 (1 for a single member council) and the ranking 1
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+pub struct QuorumPolicy {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub initial: Option<usize>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ranking: Option<usize>,
+}
+
+impl QuorumPolicy {
+    pub fn initial(&self, members: usize) -> usize {
+        self.initial.unwrap_or(members.clamp(1, 2))
+    }
+
+    pub fn ranking(&self) -> usize {
+        self.ranking.unwrap_or(1)
+    }
+}
+
+// where the stage documents are persisted
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum DocumentStoreSettings {
+    // the document-service at document_service_url
+    #[default]
+    Remote,
+    Filesystem {
+        path: String,
+    },
+    Memory,
+}
+
+// naming of the stage documents and the public links returned in the summary
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(default)]
+pub struct DocumentSettings {
+    // placeholders {member}, {stage} and {title}
+    pub name_template: String,
+    // placeholder {document}, when not set the location reported by the store is used
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub public_url: Option<String>,
+}
+
+impl Default for DocumentSettings {
+    fn default() -> Self {
+        DocumentSettings {
+            name_template: "{member}-{stage}-{title}.md".to_string(),
+            public_url: None,
+        }
+    }
+}
+
+// prompt template files (relative to the config directory), the built in
+// templates are used when not set
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(default)]
+pub struct PromptSettings {
+    // recorded with every run
+    pub version: String,
+    pub ranking: Option<String>,
+    pub revision: Option<String>,
+    pub chairman: Option<String>,
+    // member -> templates used for that member only
+    pub overrides: BTreeMap<String, PromptOverride>,
+}
+
+impl Default for PromptSettings {
+    fn default() -> Self {
+        PromptSettings {
+            version: "builtin".to_string(),
+            ranking: None,
+            revision: None,
+            chairman: None,
+            overrides: BTreeMap::new(),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+#[serde(default)]
+pub struct PromptOverride {
+    pub ranking: Option<String>,
+    pub revision: Option<String>,
+    pub chairman: Option<String>,
+}
+
+// number of revision rounds of the revise stage, each round every member revises
+// its latest answer after reading the latest answers of its peers
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(default)]
+pub struct RevisionSettings {
+    pub rounds: usize,
+}
+
+impl Default for RevisionSettings {
+    fn default() -> Self {
+        RevisionSettings { rounds: 1 }
+    }
+}
+
+// a member's breaker opens after failure_threshold consecutive failures and
+// half-opens (allows a trial) after cool_down_secs
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(default)]
+pub struct BreakerPolicy {
+    pub failure_threshold: u32,
+    pub cool_down_secs: u64,
+}
+
+impl Default for BreakerPolicy {
+    fn default() -> Self {
+        BreakerPolicy {
+            failure_threshold: 3,
+            cool_down_secs: 60,
+        }
+    }
+}
+
+// settings of the shared http client used for all member and document-service calls
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(default)]
+pub struct HttpSettings {
+    pub connect_timeout_secs: u64,
+    pub timeouts: StageTimeouts,
+    pub retry: RetryPolicy,
+}
+
+impl Default for HttpSettings {
+    fn default() -> Self {
+        HttpSettings {
+            connect_timeout_secs: 10,
+            timeouts: StageTimeouts::default(),
+            retry: RetryPolicy::default(),
+        }
+    }
+}
+
+// request timeout (in seconds) per stage, a council member can override it with timeout_secs
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(default)]
+pub struct StageTimeouts {
+    pub health: u64,
+    pub document: u64,
+    pub initial: u64,
+    pub revision: u64,
+    pub ranking: u64,
+    pub chairman: u64,
+}
+
+impl Default for StageTimeouts {
+    fn default() -> Self {
+        StageTimeouts {
+            health: 10,
+            document: 30,
+            initial: 300,
+            revision: 300,
+            ranking: 300,
+            chairman: 600,
+        }
+    }
+}
+
+impl StageTimeouts {
+    pub fn for_stage(&self, stage: &str) -> Duration {
+        let secs = match stage {
+            "health" => self.health,
+            "initial" => self.initial,
+            "revision" => self.revision,
+            "ranking" => self.ranking,
+            "chairman" => self.chairman,
+            _ => self.document,
+        };
+        Duration::from_secs(secs)
+    }
+}
+
+// retries of transient failures (connect errors, 429 and 5xx), the delay doubles
+// with every attempt (capped at max_delay_ms) with random jitter applied
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(default)]
+pub struct RetryPolicy {
+    pub max_retries: u32,
+    pub base_delay_ms: u64,
+    pub max_delay_ms: u64,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        RetryPolicy {
+            max_retries: 3,
+            base_delay_ms: 500,
+            max_delay_ms: 30000,
+        }
+    }
+}
+
+fn default_max_concurrent_runs() -> usize {
+    1
+}
+
+fn default_max_queued_runs() -> usize {
+    8
+}
+
+fn default_history_window() -> usize {
+    5
+}
+
+fn default_pipelines() -> BTreeMap<String, Vec<PipelineStage>> {
+    BTreeMap::from([(
+        "default".to_string(),
+        vec![
+            PipelineStage::Answer,
+            PipelineStage::Rank,
+            PipelineStage::Synthesize,
+        ],
+    )])
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
 pub struct ModelSchema {
     pub id: usize,
     pub name: String,
     pub url: String,
     pub model: String,
+    // overrides the stage timeout for this member
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub timeout_secs: Option<u64>,
+    // weight of the member's ranking votes in the aggregation (positive, default 1)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub weight: Option<f64>,
+    // how much the chairman should trust the member's answer, 0 to 1 (default 1)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub trust: Option<f64>,
+    #[serde(default, skip_serializing_if = "GenerationSettings::is_empty")]
+    pub generation: GenerationSettings,
+}
+
+// the stages a member can set max_tokens for
+pub const GENERATION_STAGES: [&str; 4] = ["initial", "revision", "ranking", "chairman"];
+
+// the member's generation defaults, merged with the request on every call
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq)]
+#[serde(default)]
+pub struct GenerationSettings {
+    // stage (initial, revision, ranking, chairman) -> max tokens
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub max_tokens: BTreeMap<String, usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub temperature: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub top_p: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub system_prompt: Option<String>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub stop: Vec<String>,
+}
+
+impl GenerationSettings {
+    pub fn is_empty(&self) -> bool {
+        *self == GenerationSettings::default()
+    }
+}
+
+impl ModelSchema {
+    pub fn vote_weight(&self) -> f64 {
+        self.weight.unwrap_or(1.0)
+    }
+
+    pub fn answer_trust(&self) -> f64 {
+        self.trust.unwrap_or(1.0)
+    }
 }
 
 pub trait ConfigInterface {
@@ -28,10 +335,35 @@ pub trait ConfigInterface {
 #[derive(Debug, Clone)]
 pub struct ImplConfigInterface {}
 
+impl ImplConfigInterface {
+    // reads the config with the given environment variables applied (std::env::vars()
+    // for the service, an explicit list in tests), returns the overrides applied and
+    // skipped
+    pub fn read_with_env<I>(
+        &self,
+        name: String,
+        vars: I,
+    ) -> Result<(Parameters, EnvOverrides), Box<dyn std::error::Error>>
+    where
+        I: IntoIterator<Item = (String, String)>,
+    {
+        let json_data = File::open(&name)?;
+        let mut params = serde_json::from_reader(json_data)?;
+        let overrides = apply_env_overrides(&mut params, vars)?;
+        validate_parameters(&params)?;
+        Ok((params, overrides))
+    }
+}
+
 impl ConfigInterface for ImplConfigInterface {
     fn read(&self, name: String) -> Result<Parameters, Box<dyn std::error::Error>> {
-        let json_data = File::open(&name)?;
-        let params = serde_json::from_reader(json_data)?;
+        let (params, overrides) = self.read_with_env(name, std::env::vars())?;
+        if !overrides.skipped.is_empty() {
+            log::info!(
+                "[read] environment overrides skipped (no matching setting) {}",
+                overrides.skipped.join(", ")
+            );
+        }
         Ok(params)
     }
 }
@@ -1 +1,3 @@
 pub mod load;
+pub mod overrides;
+pub mod validate;
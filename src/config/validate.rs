@@ -0,0 +1,263 @@
+use crate::api::schema::ChairmanStrategy;
+use crate::config::load::{DocumentStoreSettings, GENERATION_STAGES, ModelSchema, Parameters};
+use crate::handlers::chairman::is_council_member;
+use std::collections::BTreeSet;
+
+// semantic validation of the config
+//
+// serde only checks the structure of the file, this checks the values (unique
+// members, valid urls, port range, log level ...) and reports all the problems
+// found at once
+
+const LOG_LEVELS: [&str; 3] = ["info", "debug", "trace"];
+
+pub fn validate_parameters(params: &Parameters) -> Result<(), Box<dyn std::error::Error>> {
+    let mut errors: Vec<String> = vec![];
+
+    if !LOG_LEVELS.contains(&params.log_level.as_str()) {
+        errors.push(format!(
+            "log_level {} is not one of {}",
+            params.log_level,
+            LOG_LEVELS.join(", ")
+        ));
+    }
+    if params.server_port == 0 || params.server_port > u16::MAX as usize {
+        errors.push(format!(
+            "server_port {} is not in the range 1-{}",
+            params.server_port,
+            u16::MAX
+        ));
+    }
+
+    if params.council_members.is_empty() {
+        errors.push("council_members is empty".to_string());
+    }
+    let mut ids = BTreeSet::new();
+    let mut names = BTreeSet::new();
+    for member in params.council_members.iter() {
+        if !ids.insert(member.id) {
+            errors.push(format!("council member id {} is not unique", member.id));
+        }
+        if !names.insert(member.name.as_str()) {
+            errors.push(format!("council member name {} is not unique", member.name));
+        }
+        validate_member("council member", member, &mut errors);
+    }
+    validate_member("council_chairman", &params.council_chairman, &mut errors);
+    if params.chairman_strategy == ChairmanStrategy::External
+        && is_council_member(&params.council_chairman, &params.council_members)
+    {
+        errors.push(format!(
+            "chairman_strategy external but council_chairman {} is a council member",
+            params.council_chairman.name
+        ));
+    }
+
+    if let Some(token) = &params.admin_token
+        && token.trim().is_empty()
+    {
+        errors.push("admin_token is empty".to_string());
+    }
+
+    // without all the placeholders the documents of different members or stages
+    // would overwrite each other
+    for placeholder in ["{member}", "{stage}", "{title}"] {
+        if !params.documents.name_template.contains(placeholder) {
+            errors.push(format!(
+                "documents name_template {:?} has no {} placeholder",
+                params.documents.name_template, placeholder
+            ));
+        }
+    }
+    if let Some(public_url) = &params.documents.public_url
+        && !public_url.contains("{document}")
+    {
+        errors.push(format!(
+            "documents public_url {:?} has no {{document}} placeholder",
+            public_url
+        ));
+    }
+
+    if let DocumentStoreSettings::Remote = params.document_store
+        && let Err(e) = validate_url(&params.document_service_url)
+    {
+        errors.push(format!(
+            "document_service_url {:?} {}",
+            params.document_service_url, e
+        ));
+    }
+    if let DocumentStoreSettings::Filesystem { path } = &params.document_store
+        && path.is_empty()
+    {
+        errors.push("document_store path is empty".to_string());
+    }
+
+    // only a configured quorum is checked, the default follows the council size, a
+    // quorum of 0 would let a run continue without any answer
+    for (stage, quorum) in [
+        ("initial", params.quorum.initial),
+        ("ranking", params.quorum.ranking),
+    ] {
+        let Some(quorum) = quorum else {
+            continue;
+        };
+        if quorum == 0 {
+            errors.push(format!("quorum {} must be at least 1", stage));
+        } else if quorum > params.council_members.len() {
+            errors.push(format!(
+                "quorum {} {} exceeds the {} council members",
+                stage,
+                quorum,
+                params.council_members.len()
+            ));
+        }
+    }
+
+    if errors.is_empty() {
+        Ok(())
+    } else {
+        Err(Box::from(format!(
+            "[validate_parameters] invalid config: {}",
+            errors.join("; ")
+        )))
+    }
+}
+
+fn validate_member(kind: &str, member: &ModelSchema, errors: &mut Vec<String>) {
+    if member.name.trim().is_empty() {
+        errors.push(format!("{} {} has no name", kind, member.id));
+    }
+    if member.model.trim().is_empty() {
+        errors.push(format!("{} {} has no model", kind, member.name));
+    }
+    if let Err(e) = validate_url(&member.url) {
+        errors.push(format!(
+            "{} {} url {:?} {}",
+            kind, member.name, member.url, e
+        ));
+    }
+    if member.timeout_secs == Some(0) {
+        errors.push(format!("{} {} timeout_secs is 0", kind, member.name));
+    }
+    if let Some(weight) = member.weight
+        && !(weight.is_finite() && weight > 0.0)
+    {
+        errors.push(format!(
+            "{} {} weight {} is not a positive number",
+            kind, member.name, weight
+        ));
+    }
+    if let Some(trust) = member.trust
+        && !(0.0..=1.0).contains(&trust)
+    {
+        errors.push(format!(
+            "{} {} trust {} is not in the range 0-1",
+            kind, member.name, trust
+        ));
+    }
+    let generation = &member.generation;
+    for (stage, max_tokens) in generation.max_tokens.iter() {
+        if !GENERATION_STAGES.contains(&stage.as_str()) {
+            errors.push(format!(
+                "{} {} max_tokens stage {} is not one of {}",
+                kind,
+                member.name,
+                stage,
+                GENERATION_STAGES.join(", ")
+            ));
+        }
+        if *max_tokens == 0 {
+            errors.push(format!(
+                "{} {} max_tokens {} is 0",
+                kind, member.name, stage
+            ));
+        }
+    }
+    if let Some(temperature) = generation.temperature
+        && !(0.0..=2.0).contains(&temperature)
+    {
+        errors.push(format!(
+            "{} {} temperature {} is not in the range 0-2",
+            kind, member.name, temperature
+        ));
+    }
+    if let Some(top_p) = generation.top_p
+        && !(0.0..=1.0).contains(&top_p)
+    {
+        errors.push(format!(
+            "{} {} top_p {} is not in the range 0-1",
+            kind, member.name, top_p
+        ));
+    }
+}
+
+// an absolute http(s) url
+fn validate_url(url: &str) -> Result<(), String> {
+    let parsed = reqwest::Url::parse(url).map_err(|e| e.to_string())?;
+    match parsed.scheme() {
+        "http" | "https" if parsed.host_str().is_some() => Ok(()),
+        "http" | "https" => Err("has no host".to_string()),
+        scheme => Err(format!("has unsupported scheme {}", scheme)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::testing_parameters as parameters;
+
+    #[test]
+    fn test_valid_config() {
+        assert!(validate_parameters(&parameters()).is_ok());
+    }
+
+    #[test]
+    fn test_single_member_council() {
+        let mut params = parameters();
+        params.council_members.truncate(1);
+        params.quorum = Default::default();
+        assert!(validate_parameters(&params).is_ok());
+        assert_eq!(params.quorum.initial(1), 1);
+        assert_eq!(params.quorum.initial(4), 2);
+        params.quorum.initial = Some(2);
+        let err = validate_parameters(&params).unwrap_err().to_string();
+        assert!(err.contains("quorum initial 2 exceeds"));
+    }
+
+    #[test]
+    fn test_invalid_config() {
+        let mut params = parameters();
+        params.log_level = "verbose".to_string();
+        params.server_port = 70000;
+        params.council_members[1].id = params.council_members[0].id;
+        params.council_members[2].name = params.council_members[0].name.clone();
+        params.council_chairman.url = "gemini:8082".to_string();
+        params.document_service_url = "not a url".to_string();
+        params.council_members[3].trust = Some(1.5);
+        params.council_members[2].weight = Some(0.0);
+        params.documents.name_template = "{title}.md".to_string();
+        params.documents.public_url = Some("https://docs/read".to_string());
+        params.chairman_strategy = ChairmanStrategy::External;
+        params.quorum.initial = Some(0);
+        params.quorum.ranking = Some(0);
+        let err = validate_parameters(&params).unwrap_err().to_string();
+        for expected in [
+            "log_level verbose",
+            "server_port 70000",
+            "id 1 is not unique",
+            "name gemini is not unique",
+            "council_chairman gemini url",
+            "document_service_url",
+            "trust 1.5",
+            "weight 0 is not a positive number",
+            "has no {member} placeholder",
+            "has no {stage} placeholder",
+            "public_url \"https://docs/read\" has no {document} placeholder",
+            "chairman_strategy external",
+            "quorum initial must be at least 1",
+            "quorum ranking must be at least 1",
+        ] {
+            assert!(err.contains(expected), "{} missing in {}", expected, err);
+        }
+    }
+}
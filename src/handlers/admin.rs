@@ -0,0 +1,312 @@
+use crate::CONFIG_FILE;
+use crate::config::load::{ModelSchema, Parameters};
+use crate::config::validate::validate_parameters;
+use crate::handlers::api_calls::process_get_call;
+use crate::handlers::helper::get_http_settings;
+use crate::handlers::reload::{run_parameters, update_parameters};
+use custom_logger as log;
+use http::Method;
+use hyper::body::Bytes;
+use std::fs;
+
+// admin api for the council membership
+//
+// - GET    /v1/admin/council/members         -> the council members and the chairman
+// - POST   /v1/admin/council/members         -> adds a member
+// - PUT    /v1/admin/council/members/{name}  -> replaces a member
+// - DELETE /v1/admin/council/members/{name}  -> removes a member
+// - PUT    /v1/admin/council/chairman        -> replaces the chairman
+//
+// a member (or chairman) that is added or replaced must answer its health endpoint,
+// the changed config is validated before it is installed and only applies to new runs,
+// with ?persist=true the change is written back to the config file before it is
+// installed, so a failed write leaves the running council unchanged
+//
+// all admin routes (including /v1/admin/reload) require the admin_token of the config
+// as a bearer token, they are disabled when no token is configured
+
+pub const ADMIN_PREFIX: &str = "/v1/admin/";
+pub const COUNCIL_PREFIX: &str = "/v1/admin/council/";
+pub const RELOAD_PATH: &str = "/v1/admin/reload";
+const MEMBERS_PATH: &str = "/v1/admin/council/members";
+const CHAIRMAN_PATH: &str = "/v1/admin/council/chairman";
+
+// the routes that require the admin token, every admin route is matched on a path
+// starting with this prefix (never with contains)
+pub fn is_admin_route(path: &str) -> bool {
+    path.starts_with(ADMIN_PREFIX)
+}
+
+#[derive(Debug, Clone)]
+pub enum CouncilChange {
+    Add(ModelSchema),
+    Replace(String, ModelSchema),
+    Remove(String),
+    Chairman(ModelSchema),
+}
+
+impl CouncilChange {
+    pub fn apply(&self, params: &mut Parameters) -> Result<(), Box<dyn std::error::Error>> {
+        match self {
+            CouncilChange::Add(member) => {
+                if let Some(existing) = params
+                    .council_members
+                    .iter()
+                    .find(|x| x.name == member.name || x.id == member.id)
+                {
+                    return Err(Box::from(format!(
+                        "[apply] conflict member {} (id {}) already exists",
+                        existing.name, existing.id
+                    )));
+                }
+                params.council_members.push(member.clone());
+            }
+            CouncilChange::Replace(name, member) => {
+                let existing = params
+                    .council_members
+                    .iter_mut()
+                    .find(|x| &x.name == name)
+                    .ok_or(format!("[apply] member {} not found", name))?;
+                *existing = member.clone();
+            }
+            CouncilChange::Remove(name) => {
+                let count = params.council_members.len();
+                params.council_members.retain(|x| &x.name != name);
+                if params.council_members.len() == count {
+                    return Err(Box::from(format!("[apply] member {} not found", name)));
+                }
+            }
+            CouncilChange::Chairman(member) => params.council_chairman = member.clone(),
+        }
+        Ok(())
+    }
+
+    // the member that has to be reachable before the change is applied
+    fn member(&self) -> Option<&ModelSchema> {
+        match self {
+            CouncilChange::Add(member)
+            | CouncilChange::Replace(_, member)
+            | CouncilChange::Chairman(member) => Some(member),
+            CouncilChange::Remove(_) => None,
+        }
+    }
+}
+
+pub async fn council_admin(
+    method: &Method,
+    path: &str,
+    query: Option<&str>,
+    data: Bytes,
+) -> Result<String, Box<dyn std::error::Error>> {
+    let change = match (method, path) {
+        (&Method::GET, MEMBERS_PATH) => return council_state(),
+        (&Method::POST, MEMBERS_PATH) => CouncilChange::Add(parse_member(&data)?),
+        (&Method::PUT, CHAIRMAN_PATH) => CouncilChange::Chairman(parse_member(&data)?),
+        (&Method::PUT, x) if x.starts_with(MEMBERS_PATH) => {
+            CouncilChange::Replace(member_name(x)?, parse_member(&data)?)
+        }
+        (&Method::DELETE, x) if x.starts_with(MEMBERS_PATH) => {
+            CouncilChange::Remove(member_name(x)?)
+        }
+        _ => {
+            return Err(Box::from(format!(
+                "[council_admin] {} {} not found",
+                method, path
+            )));
+        }
+    };
+    if let Some(member) = change.member() {
+        check_member_health(member).await?;
+    }
+    let persist = query
+        .unwrap_or_default()
+        .split('&')
+        .any(|x| x == "persist=true");
+    let parameters = update_parameters(|params| {
+        change.apply(params)?;
+        if persist {
+            // only a valid change is written, the config is installed after the write
+            validate_parameters(params)
+                .map_err(|e| format!("[council_admin] invalid request {}", e))?;
+            persist_change(&change)
+                .map_err(|e| format!("[council_admin] change not applied, not persisted {}", e))?;
+        }
+        Ok(())
+    })?;
+    log::info!(
+        "[council_admin] {:?} applied ({} council members, chairman {}, persisted {})",
+        change,
+        parameters.council_members.len(),
+        parameters.council_chairman.name,
+        persist
+    );
+    council_state()
+}
+
+// checks the bearer token of an admin request against the configured admin_token
+pub fn authorize_admin(authorization: Option<&str>) -> Result<(), Box<dyn std::error::Error>> {
+    let parameters = run_parameters()?.ok_or("[authorize_admin] configuration not initialised")?;
+    let Some(token) = parameters.admin_token.as_deref() else {
+        return Err(Box::from(
+            "[authorize_admin] forbidden admin api disabled (no admin_token configured)",
+        ));
+    };
+    let presented = authorization
+        .and_then(|x| x.strip_prefix("Bearer "))
+        .unwrap_or_default();
+    if !constant_time_eq(presented.as_bytes(), token.as_bytes()) {
+        return Err(Box::from(
+            "[authorize_admin] unauthorized invalid admin token",
+        ));
+    }
+    Ok(())
+}
+
+// compares without returning early, so the time taken does not reveal the
+// length of the matching prefix
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    if a.len() != b.len() {
+        return false;
+    }
+    a.iter()
+        .zip(b.iter())
+        .fold(0u8, |acc, (x, y)| acc | (x ^ y))
+        == 0
+}
+
+fn council_state() -> Result<String, Box<dyn std::error::Error>> {
+    let parameters = run_parameters()?.ok_or("[council_state] configuration not initialised")?;
+    let content = serde_json::json!({
+        "councilMembers": parameters.council_members,
+        "councilChairman": parameters.council_chairman,
+    });
+    Ok(serde_json::to_string_pretty(&content)?)
+}
+
+fn parse_member(data: &Bytes) -> Result<ModelSchema, Box<dyn std::error::Error>> {
+    let member: ModelSchema = serde_json::from_slice(data)
+        .map_err(|e| format!("[parse_member] invalid request {}", e))?;
+    Ok(member)
+}
+
+fn member_name(path: &str) -> Result<String, Box<dyn std::error::Error>> {
+    match path
+        .strip_prefix(MEMBERS_PATH)
+        .and_then(|x| x.strip_prefix('/'))
+    {
+        Some(name) if !name.is_empty() && !name.contains('/') => Ok(name.to_string()),
+        _ => Err(Box::from(format!(
+            "[member_name] invalid request no member name in {}",
+            path
+        ))),
+    }
+}
+
+async fn check_member_health(member: &ModelSchema) -> Result<(), Box<dyn std::error::Error>> {
+    let timeout = get_http_settings()?.timeouts.for_stage("health");
+    let response = process_get_call(format!("{}/v1/health", member.url), timeout).await;
+    match response {
+        Ok(_) => Ok(()),
+        Err(e) => Err(Box::from(format!(
+            "[check_member_health] invalid request member {} is not reachable {}",
+            member.name, e
+        ))),
+    }
+}
+
+// applies the change to the config file as written, so the environment overrides
+// are not persisted, the file is replaced atomically
+fn persist_change(change: &CouncilChange) -> Result<(), Box<dyn std::error::Error>> {
+    let config_file = CONFIG_FILE
+        .lock()
+        .map_err(|_| "mutex lock failed")?
+        .clone()
+        .ok_or("[persist_change] config file not set")?;
+    let mut value: serde_json::Value = serde_json::from_str(&fs::read_to_string(&config_file)?)?;
+    let mut params: Parameters = serde_json::from_value(value.clone())?;
+    change.apply(&mut params)?;
+    value["council_members"] = serde_json::to_value(&params.council_members)?;
+    value["council_chairman"] = serde_json::to_value(&params.council_chairman)?;
+    let tmp = format!("{}.tmp", config_file);
+    fs::write(&tmp, serde_json::to_string_pretty(&value)?)?;
+    fs::rename(&tmp, &config_file)?;
+    log::info!("[persist_change] written {}", config_file);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{member, run_config, testing_parameters};
+
+    #[test]
+    fn test_council_changes() {
+        let mut params = testing_parameters();
+        let count = params.council_members.len();
+        CouncilChange::Add(member(5, "mistral"))
+            .apply(&mut params)
+            .unwrap();
+        assert_eq!(params.council_members.len(), count + 1);
+        let err = CouncilChange::Add(member(5, "other"))
+            .apply(&mut params)
+            .unwrap_err();
+        assert!(err.to_string().contains("conflict"));
+        CouncilChange::Replace("mistral".to_string(), member(5, "mistral-2"))
+            .apply(&mut params)
+            .unwrap();
+        assert!(params.council_members.iter().any(|x| x.name == "mistral-2"));
+        CouncilChange::Remove("mistral-2".to_string())
+            .apply(&mut params)
+            .unwrap();
+        assert_eq!(params.council_members.len(), count);
+        let err = CouncilChange::Remove("mistral".to_string())
+            .apply(&mut params)
+            .unwrap_err();
+        assert!(err.to_string().contains("not found"));
+        assert_eq!(
+            member_name("/v1/admin/council/members/grok").unwrap(),
+            "grok"
+        );
+        assert!(member_name("/v1/admin/council/members/").is_err());
+    }
+
+    #[test]
+    fn test_admin_routes_require_the_token() {
+        for path in [
+            RELOAD_PATH,
+            MEMBERS_PATH,
+            CHAIRMAN_PATH,
+            "/v1/admin/council/members/grok",
+        ] {
+            assert!(is_admin_route(path), "{} is not protected", path);
+        }
+        // a prefixed path is neither protected nor routed to an admin handler
+        let path = "/anything/v1/admin/reload";
+        assert!(!is_admin_route(path));
+        assert_ne!(path, RELOAD_PATH);
+        assert!(!path.starts_with(COUNCIL_PREFIX));
+    }
+
+    #[tokio::test]
+    async fn test_authorize_admin() {
+        use crate::handlers::reload::with_run_config;
+
+        let mut params = testing_parameters();
+        params.admin_token = None;
+        let err = with_run_config(run_config(params.clone()), async {
+            authorize_admin(Some("Bearer secret")).unwrap_err()
+        })
+        .await;
+        assert!(err.to_string().contains("forbidden"));
+
+        params.admin_token = Some("secret".to_string());
+        with_run_config(run_config(params), async {
+            assert!(authorize_admin(Some("Bearer secret")).is_ok());
+            let err = authorize_admin(Some("Bearer secreT")).unwrap_err();
+            assert!(err.to_string().contains("unauthorized"));
+            assert!(authorize_admin(Some("secret")).is_err());
+            assert!(authorize_admin(None).is_err());
+        })
+        .await;
+    }
+}
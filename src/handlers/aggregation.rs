@@ -0,0 +1,388 @@
+use crate::api::schema::{AggregateResult, AggregationStrategy};
+use custom_logger as log;
+use std::collections::BTreeMap;
+
+// rank aggregation strategies
+//
+// - borda     -> n-1 points for first place down to 0 for last, missing candidates score 0
+// - mean-rank -> mean of the 1 based positions, missing candidates are ranked last (n)
+// - copeland  -> pairwise contests, 1 point per win and 0.5 per tie
+// - kemeny    -> the ordering that agrees with the most pairwise preferences (small councils only)
+//
+// ties are broken by the mean rank and finally by the candidate label so that
+// the result is always deterministic
+//
+// candidates excluded from a ballot (e.g. the judge's own response) are treated as
+// abstentions, the ballot neither rewards nor penalises them
+//
+// every ballot counts with the weight of its judge (borda points, pairwise
+// preferences and the mean rank are weighted), the default weight is 1
+
+// 8! orderings is the most we are prepared to enumerate
+pub const KEMENY_MAX_CANDIDATES: usize = 8;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Ballot {
+    pub judge: String,
+    pub ranking: Vec<String>,
+    pub excluded: Vec<String>,
+    pub weight: f64,
+}
+
+impl Ballot {
+    // number of candidates this ballot votes on
+    fn field_size(&self, candidates: &[String]) -> usize {
+        candidates
+            .iter()
+            .filter(|x| !self.excluded.contains(x))
+            .count()
+    }
+}
+
+pub fn aggregate(
+    strategy: AggregationStrategy,
+    ballots: &[Ballot],
+    candidates: &[String],
+) -> AggregateResult {
+    let pairwise = pairwise_preferences(ballots, candidates);
+    let mean_ranks = mean_ranks(ballots, candidates);
+    let mut result = AggregateResult {
+        strategy,
+        ballots: ballots
+            .iter()
+            .map(|x| (x.judge.clone(), x.ranking.clone()))
+            .collect(),
+        ..Default::default()
+    };
+    if ballots.iter().any(|x| x.weight != 1.0) {
+        result.weights = ballots
+            .iter()
+            .map(|x| (x.judge.clone(), x.weight))
+            .collect();
+    }
+    match strategy {
+        AggregationStrategy::Borda => {
+            result.scores = borda_scores(ballots, candidates);
+            result.higher_is_better = true;
+        }
+        AggregationStrategy::MeanRank => {
+            result.scores = mean_ranks.clone();
+            result.higher_is_better = false;
+        }
+        AggregationStrategy::Copeland => {
+            result.scores = copeland_scores(&pairwise, candidates);
+            result.higher_is_better = true;
+        }
+        AggregationStrategy::Kemeny => {
+            if candidates.len() > KEMENY_MAX_CANDIDATES {
+                log::info!(
+                    "[aggregate] {} candidates exceeds the kemeny limit of {}, using copeland",
+                    candidates.len(),
+                    KEMENY_MAX_CANDIDATES
+                );
+                return aggregate(AggregationStrategy::Copeland, ballots, candidates);
+            }
+            let (ranking, score) = kemeny_ranking(&pairwise, candidates);
+            let n = ranking.len();
+            result.scores = ranking
+                .iter()
+                .enumerate()
+                .map(|(i, x)| (x.clone(), (n - 1 - i) as f64))
+                .collect();
+            result.higher_is_better = true;
+            result.kemeny_score = Some(score);
+        }
+    }
+    result.ranking = order_candidates(&result.scores, result.higher_is_better, &mean_ranks);
+    result.pairwise = pairwise;
+    result
+}
+
+// weighted number of ballots ranking a above b, a ranked candidate beats a missing one
+fn pairwise_preferences(
+    ballots: &[Ballot],
+    candidates: &[String],
+) -> BTreeMap<String, BTreeMap<String, f64>> {
+    let mut pairwise: BTreeMap<String, BTreeMap<String, f64>> = BTreeMap::new();
+    for a in candidates.iter() {
+        let row = pairwise.entry(a.clone()).or_default();
+        for b in candidates.iter().filter(|x| *x != a) {
+            row.insert(b.clone(), 0.0);
+        }
+    }
+    for ballot in ballots.iter() {
+        for a in candidates.iter().filter(|x| !ballot.excluded.contains(x)) {
+            for b in candidates
+                .iter()
+                .filter(|x| *x != a && !ballot.excluded.contains(x))
+            {
+                let pos_a = ballot.ranking.iter().position(|x| x == a);
+                let pos_b = ballot.ranking.iter().position(|x| x == b);
+                let prefers = match (pos_a, pos_b) {
+                    (Some(x), Some(y)) => x < y,
+                    (Some(_), None) => true,
+                    _ => false,
+                };
+                if prefers && let Some(value) = pairwise.get_mut(a).and_then(|x| x.get_mut(b)) {
+                    *value += ballot.weight;
+                }
+            }
+        }
+    }
+    pairwise
+}
+
+fn borda_scores(ballots: &[Ballot], candidates: &[String]) -> BTreeMap<String, f64> {
+    let mut scores: BTreeMap<String, f64> = candidates.iter().map(|x| (x.clone(), 0.0)).collect();
+    for ballot in ballots.iter() {
+        let n = ballot.field_size(candidates);
+        for (i, label) in ballot.ranking.iter().enumerate() {
+            if let Some(score) = scores.get_mut(label) {
+                *score += n.saturating_sub(1 + i) as f64 * ballot.weight;
+            }
+        }
+    }
+    scores
+}
+
+fn mean_ranks(ballots: &[Ballot], candidates: &[String]) -> BTreeMap<String, f64> {
+    let n = candidates.len();
+    let mut ranks: BTreeMap<String, f64> = BTreeMap::new();
+    for label in candidates.iter() {
+        let voting: Vec<&Ballot> = ballots
+            .iter()
+            .filter(|x| !x.excluded.contains(label))
+            .collect();
+        let total: f64 = voting
+            .iter()
+            .map(|ballot| {
+                ballot
+                    .ranking
+                    .iter()
+                    .position(|x| x == label)
+                    .map_or(ballot.field_size(candidates) as f64, |x| (x + 1) as f64)
+                    * ballot.weight
+            })
+            .sum();
+        let weights: f64 = voting.iter().map(|x| x.weight).sum();
+        let mean = if weights <= 0.0 {
+            n as f64
+        } else {
+            total / weights
+        };
+        ranks.insert(label.clone(), mean);
+    }
+    ranks
+}
+
+fn copeland_scores(
+    pairwise: &BTreeMap<String, BTreeMap<String, f64>>,
+    candidates: &[String],
+) -> BTreeMap<String, f64> {
+    let mut scores: BTreeMap<String, f64> = BTreeMap::new();
+    for a in candidates.iter() {
+        let mut score = 0.0;
+        for b in candidates.iter().filter(|x| *x != a) {
+            let a_over_b = pairwise[a][b];
+            let b_over_a = pairwise[b][a];
+            if a_over_b > b_over_a {
+                score += 1.0;
+            } else if a_over_b == b_over_a {
+                score += 0.5;
+            }
+        }
+        scores.insert(a.clone(), score);
+    }
+    scores
+}
+
+// enumerates all orderings (in lexicographic order, so ties resolve to the first)
+// and returns the one with the highest sum of agreeing pairwise preferences
+fn kemeny_ranking(
+    pairwise: &BTreeMap<String, BTreeMap<String, f64>>,
+    candidates: &[String],
+) -> (Vec<String>, f64) {
+    let mut current: Vec<String> = candidates.to_vec();
+    current.sort();
+    let mut best = current.clone();
+    let mut best_score = f64::MIN;
+    loop {
+        let mut score = 0.0;
+        for i in 0..current.len() {
+            for j in (i + 1)..current.len() {
+                score += pairwise[&current[i]][&current[j]];
+            }
+        }
+        if score > best_score {
+            best_score = score;
+            best = current.clone();
+        }
+        if !next_permutation(&mut current) {
+            break;
+        }
+    }
+    (best, best_score)
+}
+
+fn next_permutation(items: &mut [String]) -> bool {
+    if items.len() < 2 {
+        return false;
+    }
+    let mut i = items.len() - 1;
+    while i > 0 && items[i - 1] >= items[i] {
+        i -= 1;
+    }
+    if i == 0 {
+        return false;
+    }
+    let mut j = items.len() - 1;
+    while items[j] <= items[i - 1] {
+        j -= 1;
+    }
+    items.swap(i - 1, j);
+    items[i..].reverse();
+    true
+}
+
+fn order_candidates(
+    scores: &BTreeMap<String, f64>,
+    higher_is_better: bool,
+    mean_ranks: &BTreeMap<String, f64>,
+) -> Vec<String> {
+    let mut ranking: Vec<String> = scores.keys().cloned().collect();
+    ranking.sort_by(|a, b| {
+        let by_score = if higher_is_better {
+            scores[b].total_cmp(&scores[a])
+        } else {
+            scores[a].total_cmp(&scores[b])
+        };
+        by_score
+            .then_with(|| mean_ranks[a].total_cmp(&mean_ranks[b]))
+            .then_with(|| a.cmp(b))
+    });
+    ranking
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ballot(judge: &str, ranking: &[&str]) -> Ballot {
+        Ballot {
+            judge: judge.to_string(),
+            ranking: ranking.iter().map(|x| x.to_string()).collect(),
+            excluded: vec![],
+            weight: 1.0,
+        }
+    }
+
+    fn candidates() -> Vec<String> {
+        ["Response A", "Response B", "Response C", "Response D"]
+            .iter()
+            .map(|x| x.to_string())
+            .collect()
+    }
+
+    fn ballots() -> Vec<Ballot> {
+        vec![
+            ballot(
+                "j1",
+                &["Response C", "Response A", "Response B", "Response D"],
+            ),
+            ballot(
+                "j2",
+                &["Response C", "Response B", "Response D", "Response A"],
+            ),
+            ballot(
+                "j3",
+                &["Response D", "Response A", "Response B", "Response C"],
+            ),
+            ballot(
+                "j4",
+                &["Response C", "Response A", "Response B", "Response D"],
+            ),
+        ]
+    }
+
+    #[test]
+    fn test_aggregate_borda() {
+        let result = aggregate(AggregationStrategy::Borda, &ballots(), &candidates());
+        assert_eq!(result.scores["Response C"], 9.0);
+        assert_eq!(result.scores["Response A"], 6.0);
+        assert_eq!(result.ranking[0], "Response C");
+        assert_eq!(result.pairwise["Response C"]["Response D"], 3.0);
+    }
+
+    #[test]
+    fn test_aggregate_mean_rank_missing_as_last() {
+        let partial = vec![
+            ballot("j1", &["Response A"]),
+            ballot("j2", &["Response B", "Response A"]),
+        ];
+        let result = aggregate(AggregationStrategy::MeanRank, &partial, &candidates());
+        assert_eq!(result.scores["Response A"], 1.5);
+        assert_eq!(result.scores["Response B"], 2.5);
+        assert_eq!(result.scores["Response C"], 4.0);
+        // C and D are tied and resolved by label
+        assert_eq!(
+            result.ranking,
+            vec!["Response A", "Response B", "Response C", "Response D"]
+        );
+    }
+
+    #[test]
+    fn test_aggregate_copeland_and_kemeny() {
+        let copeland = aggregate(AggregationStrategy::Copeland, &ballots(), &candidates());
+        assert_eq!(copeland.scores["Response C"], 3.0);
+        assert_eq!(copeland.ranking[0], "Response C");
+
+        let kemeny = aggregate(AggregationStrategy::Kemeny, &ballots(), &candidates());
+        assert_eq!(
+            kemeny.ranking,
+            vec!["Response C", "Response A", "Response B", "Response D"]
+        );
+        assert!(kemeny.kemeny_score.is_some());
+    }
+
+    #[test]
+    fn test_aggregate_weighted_ballots() {
+        let mut ballots = ballots();
+        // j3 is the only judge preferring D, with a triple weight D overtakes A and B
+        ballots[2].weight = 3.0;
+        ballots[0].weight = 0.5;
+        let borda = aggregate(AggregationStrategy::Borda, &ballots, &candidates());
+        assert_eq!(borda.scores["Response C"], 7.5);
+        assert_eq!(borda.scores["Response D"], 10.0);
+        assert_eq!(borda.ranking[0], "Response D");
+        assert_eq!(borda.pairwise["Response D"]["Response C"], 3.0);
+        assert_eq!(borda.weights["j3"], 3.0);
+
+        let mean = aggregate(AggregationStrategy::MeanRank, &ballots, &candidates());
+        assert_eq!(mean.scores["Response D"], 12.0 / 5.5);
+        assert!(
+            aggregate(AggregationStrategy::Borda, &self::ballots(), &candidates())
+                .weights
+                .is_empty()
+        );
+    }
+
+    #[test]
+    fn test_aggregate_excluded_self_votes() {
+        let mut own_a = ballot("a", &["Response B", "Response C", "Response D"]);
+        own_a.excluded = vec!["Response A".to_string()];
+        let mut own_b = ballot("b", &["Response A", "Response C", "Response D"]);
+        own_b.excluded = vec!["Response B".to_string()];
+        let ballots = vec![own_a, own_b];
+
+        let borda = aggregate(AggregationStrategy::Borda, &ballots, &candidates());
+        assert_eq!(borda.scores["Response A"], 2.0);
+        assert_eq!(borda.scores["Response B"], 2.0);
+        assert_eq!(borda.scores["Response C"], 2.0);
+
+        // an excluded candidate is not ranked last by the excluding judge
+        let mean = aggregate(AggregationStrategy::MeanRank, &ballots, &candidates());
+        assert_eq!(mean.scores["Response A"], 1.0);
+        assert_eq!(mean.scores["Response D"], 3.0);
+        assert_eq!(mean.pairwise["Response A"]["Response B"], 0.0);
+    }
+}
@@ -1,40 +1,73 @@
-use crate::api::schema::ResponseObject;
-use crate::config::load::ModelSchema;
-use crate::handlers::helper::get_document_store_url;
+use crate::api::schema::{ChatRequest, DocumentWriteRequest, ResponseObject};
+use crate::config::load::{HttpSettings, ModelSchema, RetryPolicy};
+use crate::handlers::documents::document_name;
+use crate::handlers::helper::{get_document_store, get_http_client, get_http_settings};
+use crate::handlers::labels::{SplitMix64, hash_str};
+use crate::handlers::openai::to_member_reply;
 use custom_logger as log;
 use hyper::StatusCode;
-use reqwest::Client;
+use reqwest::{Client, RequestBuilder, Response};
 use std::collections::BTreeMap;
-use std::fs;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
 // api calls
 
+// all calls share a single client (and its connection pool), the request timeout
+// is set per call as it differs per stage and member
+pub fn build_http_client(settings: &HttpSettings) -> Result<Client, Box<dyn std::error::Error>> {
+    let client = Client::builder()
+        .danger_accept_invalid_certs(true)
+        .connect_timeout(Duration::from_secs(settings.connect_timeout_secs))
+        .pool_idle_timeout(Duration::from_secs(90))
+        .build()?;
+    Ok(client)
+}
+
+// reads the stage documents of the council members, members without a
+// (readable) document are returned separately with the reason
 pub async fn get_all_documents(
     mut council_members: Vec<ModelSchema>,
-    title: String,
-) -> Result<BTreeMap<String, String>, Box<dyn std::error::Error>> {
+    stage: &str,
+    title: &str,
+) -> Result<(BTreeMap<String, String>, BTreeMap<String, String>), Box<dyn std::error::Error>> {
     let mut hm_results: BTreeMap<String, String> = BTreeMap::new();
-    let base_url = get_document_store_url()?;
+    let mut hm_missing: BTreeMap<String, String> = BTreeMap::new();
+    let store = get_document_store()?;
     council_members.sort_by_key(|x| x.id);
     for ms in council_members.clone().iter() {
-        let doc_url = format!(
-            "{}/read?document={}-{}.md",
-            base_url,
-            ms.name.clone(),
-            title
-        );
-        let response = process_get_call(doc_url).await?;
-        hm_results.insert(ms.name.clone(), response);
+        let document = document_name(&ms.name, stage, title)?;
+        match store.read(&document).await {
+            Ok(response) => {
+                hm_results.insert(ms.name.clone(), response);
+            }
+            Err(e) => {
+                log::error!("[get_all_documents] {} {} {}", ms.name, document, e);
+                hm_missing.insert(ms.name.clone(), format!("document not available {}", e));
+            }
+        }
     }
-    Ok(hm_results)
+    Ok((hm_results, hm_missing))
 }
 
-pub async fn process_get_call(url: String) -> Result<String, Box<dyn std::error::Error>> {
-    let client = Client::builder()
-        .danger_accept_invalid_certs(true)
-        .build()?;
+pub async fn get_document(
+    name: &str,
+    stage: &str,
+    title: &str,
+) -> Result<String, Box<dyn std::error::Error>> {
+    let store = get_document_store()?;
+    let document = document_name(name, stage, title)?;
+    store.read(&document).await
+}
+
+pub async fn process_get_call(
+    url: String,
+    timeout: Duration,
+) -> Result<String, Box<dyn std::error::Error>> {
+    let client = get_http_client()?;
+    let retry = get_http_settings()?.retry;
     log::trace!("[process_get_call] {}", url);
-    let client_response = client.get(url).send().await?;
+    let request = client.get(url.clone()).timeout(timeout);
+    let client_response = send_with_retry(&url, request, &retry).await?;
 
     if client_response.status() != StatusCode::OK {
         return Err(Box::from(format!(
@@ -49,24 +82,37 @@ pub async fn process_get_call(url: String) -> Result<String, Box<dyn std::error:
 }
 
 // this is a complex post as it will call the endpoint
-// if successfull will then store the document
+// if successfull will then parse the chat completion and store the assistant text
+// in the document store under the given document name
+// a document store failure after the member answered, it is not a failure of the
+// member so it does not count against the member's circuit breaker
+#[derive(Debug)]
+pub struct StoreError(pub String);
+
+impl std::fmt::Display for StoreError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl std::error::Error for StoreError {}
+
 pub async fn process_post_call(
     name: String,
     url: String,
-    doc_url: String,
-    title: String,
-    data: String,
+    document: String,
+    data: ChatRequest,
+    timeout: Duration,
 ) -> Result<ResponseObject, Box<dyn std::error::Error>> {
-    let client = Client::builder()
-        .danger_accept_invalid_certs(true)
-        .build()?;
-    let client_response = client
+    let client = get_http_client()?;
+    let retry = get_http_settings()?.retry;
+    let request = client
         .post(url)
         .header("Content-Type", "application/json")
         .header("unikernel-access", "valid")
-        .body(data)
-        .send()
-        .await?;
+        .timeout(timeout)
+        .body(serde_json::to_string(&data)?);
+    let client_response = send_with_retry(&name, request, &retry).await?;
 
     let status = client_response.status();
     let response = client_response.bytes().await?;
@@ -74,36 +120,161 @@ pub async fn process_post_call(
     let res = match status {
         StatusCode::OK => {
             // only if we have success can we then save the document
-            let doc_content = String::from_utf8(response.to_vec())?;
-            let doc_data = format!(
-                r##"{{ "document": "{}-{}.md", "content" : {} }}"##,
-                name, title, doc_content
-            );
-            fs::write(format!("docs/{}-{}.md", name, title), doc_content.clone())?;
-            let doc_url = format!("{}/write", doc_url);
-            log::info!("[process_post_call] writing document {}-{}", name, title);
-            log::debug!("[process_post_call] contents {}", doc_content);
-
-            let doc_response = client
-                .post(doc_url)
-                .header("Content-Type", "application/text")
-                .header("unikernel-access", "valid")
-                .body(doc_data)
-                .send()
-                .await?;
+            let reply = to_member_reply(str::from_utf8(&response)?)
+                .map_err(|e| format!("[process_post_call] {} {}", name, e))?;
+            log::info!("[process_post_call] writing document {}", document);
+            log::debug!("[process_post_call] contents {}", reply.text);
+            let store = get_document_store()?;
+            store.write(&document, &reply.text).await.map_err(|e| {
+                StoreError(format!("[process_post_call] {} document write {}", name, e))
+            })?;
 
             ResponseObject {
-                status_code: doc_response.status().as_u16(),
-                contents: doc_response.text().await?.clone(),
+                status_code: status.as_u16(),
+                contents: format!("document {} stored", document),
                 process_name: name,
+                reply: Some(reply),
             }
         }
         _ => ResponseObject {
             contents: String::from_utf8(response.to_vec())?,
             status_code: status.as_u16(),
             process_name: name,
+            reply: None,
         },
     };
     log::debug!("[process_post_call] response {:?}", res);
     Ok(res)
 }
+
+// writes a document to the document-service
+pub async fn process_document_write(
+    url: String,
+    data: DocumentWriteRequest,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let client = get_http_client()?;
+    let settings = get_http_settings()?;
+    let request = client
+        .post(url.clone())
+        .header("Content-Type", "application/text")
+        .header("unikernel-access", "valid")
+        .timeout(settings.timeouts.for_stage("document"))
+        .body(serde_json::to_string(&data)?);
+    let client_response = send_with_retry(&url, request, &settings.retry).await?;
+    if client_response.status() != StatusCode::OK {
+        return Err(Box::from(format!(
+            "[process_document_write] error status code {} {}",
+            client_response.status(),
+            client_response.text().await.unwrap_or_default()
+        )));
+    }
+    Ok(())
+}
+
+// sends the request, transient failures (connect errors, 429 and 5xx) are retried
+// with exponential backoff and jitter, a Retry-After (in seconds) from the service
+// is used instead of the backoff (capped at max_delay_ms)
+async fn send_with_retry(
+    target: &str,
+    request: RequestBuilder,
+    retry: &RetryPolicy,
+) -> Result<Response, Box<dyn std::error::Error>> {
+    let seed = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|x| x.as_nanos() as u64)
+        .unwrap_or(0);
+    let mut rng = SplitMix64::new(seed ^ hash_str(target));
+    let mut attempt: u32 = 0;
+    loop {
+        let current = request
+            .try_clone()
+            .ok_or("[send_with_retry] request can not be cloned")?;
+        let delay = match current.send().await {
+            Ok(response) if is_transient(response.status()) && attempt < retry.max_retries => {
+                log::info!(
+                    "[send_with_retry] {} status {} attempt {} of {}",
+                    target,
+                    response.status(),
+                    attempt + 1,
+                    retry.max_retries + 1
+                );
+                match retry_after(&response) {
+                    Some(delay) => delay.min(Duration::from_millis(retry.max_delay_ms)),
+                    None => backoff_delay(retry, attempt, rng.next_u64()),
+                }
+            }
+            Err(e) if e.is_connect() && attempt < retry.max_retries => {
+                log::info!(
+                    "[send_with_retry] {} connect error {} attempt {} of {}",
+                    target,
+                    e,
+                    attempt + 1,
+                    retry.max_retries + 1
+                );
+                backoff_delay(retry, attempt, rng.next_u64())
+            }
+            Ok(response) => return Ok(response),
+            Err(e) => return Err(Box::from(format!("[send_with_retry] {} {}", target, e))),
+        };
+        log::debug!("[send_with_retry] {} retrying in {:?}", target, delay);
+        tokio::time::sleep(delay).await;
+        attempt += 1;
+    }
+}
+
+fn is_transient(status: StatusCode) -> bool {
+    status == StatusCode::TOO_MANY_REQUESTS || status.is_server_error()
+}
+
+// only the delay-seconds form is supported, an http-date falls back to the backoff
+fn retry_after(response: &Response) -> Option<Duration> {
+    response
+        .headers()
+        .get(reqwest::header::RETRY_AFTER)
+        .and_then(|x| x.to_str().ok())
+        .and_then(|x| x.trim().parse::<u64>().ok())
+        .map(Duration::from_secs)
+}
+
+// base * 2^attempt capped at max, with "equal jitter" (half fixed, half random)
+fn backoff_delay(retry: &RetryPolicy, attempt: u32, random: u64) -> Duration {
+    let exp = retry
+        .base_delay_ms
+        .saturating_mul(1u64 << attempt.min(20))
+        .min(retry.max_delay_ms);
+    let half = exp / 2;
+    let jitter = if half == 0 { 0 } else { random % (half + 1) };
+    Duration::from_millis(exp - half + jitter)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_backoff_delay() {
+        let retry = RetryPolicy {
+            max_retries: 5,
+            base_delay_ms: 100,
+            max_delay_ms: 1000,
+        };
+        assert_eq!(backoff_delay(&retry, 0, 0), Duration::from_millis(50));
+        assert_eq!(backoff_delay(&retry, 0, 50), Duration::from_millis(100));
+        assert_eq!(backoff_delay(&retry, 2, 0), Duration::from_millis(200));
+        // capped at max_delay_ms
+        assert_eq!(backoff_delay(&retry, 8, 500), Duration::from_millis(1000));
+        let mut rng = SplitMix64::new(1);
+        for attempt in 0..10 {
+            let delay = backoff_delay(&retry, attempt, rng.next_u64());
+            assert!(delay <= Duration::from_millis(1000));
+        }
+    }
+
+    #[test]
+    fn test_is_transient() {
+        assert!(is_transient(StatusCode::TOO_MANY_REQUESTS));
+        assert!(is_transient(StatusCode::BAD_GATEWAY));
+        assert!(!is_transient(StatusCode::BAD_REQUEST));
+        assert!(!is_transient(StatusCode::OK));
+    }
+}
@@ -0,0 +1,176 @@
+use crate::BREAKERS;
+use crate::api::schema::{BreakerState, BreakerStatus};
+use crate::config::load::BreakerPolicy;
+use crate::handlers::helper::get_breaker_policy;
+use custom_logger as log;
+use std::collections::BTreeMap;
+use std::time::{Duration, Instant};
+
+// circuit breaker per council member
+//
+// closed    -> calls are made, consecutive failures are counted (a success resets the count)
+// open      -> the member is skipped when assembling the council for a run
+// half-open -> after the cool-down the member joins the next run as a trial,
+//              a success closes the breaker and a failure opens it again
+//
+// failures are recorded from the health checks and from the member calls, a
+// successful health check does not close the breaker (the health endpoint can be
+// up while the completions fail), only a successful member call does
+
+#[derive(Debug, Clone, Default)]
+pub struct CircuitBreaker {
+    state: BreakerState,
+    consecutive_failures: u32,
+    opened_at: Option<Instant>,
+    last_error: Option<String>,
+}
+
+impl CircuitBreaker {
+    // returns true when calls to the member are allowed, an open breaker
+    // moves to half-open once the cool-down has passed
+    pub fn allows(&mut self, policy: &BreakerPolicy, now: Instant) -> bool {
+        match self.state {
+            BreakerState::Closed | BreakerState::HalfOpen => true,
+            BreakerState::Open => {
+                let cool_down = Duration::from_secs(policy.cool_down_secs);
+                match self.opened_at {
+                    Some(at) if now.duration_since(at) < cool_down => false,
+                    _ => {
+                        self.state = BreakerState::HalfOpen;
+                        true
+                    }
+                }
+            }
+        }
+    }
+
+    pub fn on_success(&mut self) {
+        self.state = BreakerState::Closed;
+        self.consecutive_failures = 0;
+        self.opened_at = None;
+        self.last_error = None;
+    }
+
+    pub fn on_failure(&mut self, policy: &BreakerPolicy, reason: String, now: Instant) {
+        self.consecutive_failures += 1;
+        self.last_error = Some(reason);
+        let trip = self.state == BreakerState::HalfOpen
+            || self.consecutive_failures >= policy.failure_threshold;
+        if trip && self.state != BreakerState::Open {
+            self.state = BreakerState::Open;
+            self.opened_at = Some(now);
+        }
+    }
+
+    pub fn status(&self, policy: &BreakerPolicy, now: Instant) -> BreakerStatus {
+        let retry_in_secs = match (self.state, self.opened_at) {
+            (BreakerState::Open, Some(at)) => Some(
+                Duration::from_secs(policy.cool_down_secs)
+                    .saturating_sub(now.duration_since(at))
+                    .as_secs(),
+            ),
+            _ => None,
+        };
+        BreakerStatus {
+            state: self.state,
+            consecutive_failures: self.consecutive_failures,
+            last_error: self.last_error.clone(),
+            retry_in_secs,
+        }
+    }
+}
+
+pub fn breaker_allows(member: &str) -> Result<bool, Box<dyn std::error::Error>> {
+    let policy = get_breaker_policy()?;
+    let mut breakers = BREAKERS.lock().map_err(|_| "mutex lock failed")?;
+    let breaker = breakers.entry(member.to_string()).or_default();
+    Ok(breaker.allows(&policy, Instant::now()))
+}
+
+pub fn record_success(member: &str) -> Result<(), Box<dyn std::error::Error>> {
+    let mut breakers = BREAKERS.lock().map_err(|_| "mutex lock failed")?;
+    let breaker = breakers.entry(member.to_string()).or_default();
+    if breaker.state != BreakerState::Closed {
+        log::info!("[record_success] {} breaker closed", member);
+    }
+    breaker.on_success();
+    Ok(())
+}
+
+pub fn record_failure(member: &str, reason: String) -> Result<(), Box<dyn std::error::Error>> {
+    let policy = get_breaker_policy()?;
+    let mut breakers = BREAKERS.lock().map_err(|_| "mutex lock failed")?;
+    let breaker = breakers.entry(member.to_string()).or_default();
+    let was_open = breaker.state == BreakerState::Open;
+    breaker.on_failure(&policy, reason, Instant::now());
+    if !was_open && breaker.state == BreakerState::Open {
+        log::error!(
+            "[record_failure] {} breaker opened after {} consecutive failures",
+            member,
+            breaker.consecutive_failures
+        );
+    }
+    Ok(())
+}
+
+pub fn breaker_states() -> Result<BTreeMap<String, BreakerStatus>, Box<dyn std::error::Error>> {
+    let policy = get_breaker_policy()?;
+    let breakers = BREAKERS.lock().map_err(|_| "mutex lock failed")?;
+    let now = Instant::now();
+    Ok(breakers
+        .iter()
+        .map(|(name, breaker)| (name.clone(), breaker.status(&policy, now)))
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn policy() -> BreakerPolicy {
+        BreakerPolicy {
+            failure_threshold: 2,
+            cool_down_secs: 30,
+        }
+    }
+
+    #[test]
+    fn test_breaker_opens_and_half_opens() {
+        let policy = policy();
+        let start = Instant::now();
+        let mut breaker = CircuitBreaker::default();
+        breaker.on_failure(&policy, "timeout".to_string(), start);
+        assert!(breaker.allows(&policy, start));
+        breaker.on_failure(&policy, "timeout".to_string(), start);
+        assert_eq!(breaker.state, BreakerState::Open);
+        assert!(!breaker.allows(&policy, start + Duration::from_secs(10)));
+        assert_eq!(
+            breaker
+                .status(&policy, start + Duration::from_secs(10))
+                .retry_in_secs,
+            Some(20)
+        );
+        // after the cool-down a single failure re-opens the breaker
+        let later = start + Duration::from_secs(31);
+        assert!(breaker.allows(&policy, later));
+        assert_eq!(breaker.state, BreakerState::HalfOpen);
+        breaker.on_failure(&policy, "503".to_string(), later);
+        assert_eq!(breaker.state, BreakerState::Open);
+        assert!(!breaker.allows(&policy, later + Duration::from_secs(1)));
+    }
+
+    #[test]
+    fn test_breaker_success_closes() {
+        let policy = policy();
+        let start = Instant::now();
+        let mut breaker = CircuitBreaker::default();
+        breaker.on_failure(&policy, "timeout".to_string(), start);
+        breaker.on_failure(&policy, "timeout".to_string(), start);
+        assert!(breaker.allows(&policy, start + Duration::from_secs(30)));
+        breaker.on_success();
+        let status = breaker.status(&policy, start);
+        assert_eq!(status.state, BreakerState::Closed);
+        assert_eq!(status.consecutive_failures, 0);
+        assert_eq!(status.last_error, None);
+    }
+}
@@ -0,0 +1,169 @@
+use crate::CHAIRMAN_ROTATION;
+use crate::api::schema::{AggregateResult, ChairmanSelection, ChairmanStrategy};
+use crate::config::load::ModelSchema;
+use std::sync::atomic::Ordering;
+
+// chairman selection
+//
+// the chairman is selected when the synthesize stage starts, only the members that
+// answered the prompt can be selected by the top-ranked and round-robin strategies,
+// the selected chairman and the reason are reported in the run summary
+
+pub fn select_chairman(
+    strategy: ChairmanStrategy,
+    configured: &ModelSchema,
+    council: &[ModelSchema],
+    answered: &[String],
+    rankings: &AggregateResult,
+) -> Result<(ModelSchema, ChairmanSelection), Box<dyn std::error::Error>> {
+    let candidates: Vec<&ModelSchema> = council
+        .iter()
+        .filter(|x| answered.contains(&x.name))
+        .collect();
+    let (chairman, reason) = match strategy {
+        ChairmanStrategy::Fixed => (configured.clone(), "configured chairman".to_string()),
+        ChairmanStrategy::External => {
+            if is_council_member(configured, council) {
+                return Err(Box::from(format!(
+                    "[select_chairman] invalid request chairman {} is a council member",
+                    configured.name
+                )));
+            }
+            (
+                configured.clone(),
+                "configured chairman, not a council member".to_string(),
+            )
+        }
+        ChairmanStrategy::TopRanked => {
+            let winner = rankings
+                .ranking
+                .iter()
+                .find_map(|name| candidates.iter().find(|x| &x.name == name));
+            match winner {
+                Some(member) => (
+                    (*member).clone(),
+                    format!("answer ranked first ({:?})", rankings.strategy),
+                ),
+                // the rank stage was skipped or no ranking was valid
+                None => (
+                    configured.clone(),
+                    "no aggregated ranking, configured chairman".to_string(),
+                ),
+            }
+        }
+        ChairmanStrategy::RoundRobin => {
+            if candidates.is_empty() {
+                return Err(Box::from(
+                    "[select_chairman] no council member answered, no round-robin chairman",
+                ));
+            }
+            let turn = CHAIRMAN_ROTATION.fetch_add(1, Ordering::Relaxed);
+            let member = candidates[turn % candidates.len()];
+            (
+                member.clone(),
+                format!(
+                    "round-robin turn {} of {} members",
+                    turn % candidates.len() + 1,
+                    candidates.len()
+                ),
+            )
+        }
+    };
+    let selection = ChairmanSelection {
+        name: chairman.name.clone(),
+        model: chairman.model.clone(),
+        strategy,
+        reason,
+    };
+    Ok((chairman, selection))
+}
+
+// a chairman is a council member when it has the same name, or the same model
+// served from the same url
+pub fn is_council_member(chairman: &ModelSchema, council: &[ModelSchema]) -> bool {
+    council
+        .iter()
+        .any(|x| x.name == chairman.name || (x.url == chairman.url && x.model == chairman.model))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::member;
+
+    #[test]
+    fn test_select_chairman() {
+        let council = vec![member(1, "gemini"), member(2, "claude"), member(3, "grok")];
+        let answered = vec!["claude".to_string(), "grok".to_string()];
+        let configured = member(9, "judge");
+        let rankings = AggregateResult {
+            ranking: vec![
+                "gemini".to_string(),
+                "grok".to_string(),
+                "claude".to_string(),
+            ],
+            ..Default::default()
+        };
+
+        let (chairman, selection) = select_chairman(
+            ChairmanStrategy::TopRanked,
+            &configured,
+            &council,
+            &answered,
+            &rankings,
+        )
+        .unwrap();
+        // gemini did not answer, the first ranked member that answered is selected
+        assert_eq!(chairman.name, "grok");
+        assert_eq!(selection.strategy, ChairmanStrategy::TopRanked);
+
+        let (chairman, selection) = select_chairman(
+            ChairmanStrategy::TopRanked,
+            &configured,
+            &council,
+            &answered,
+            &AggregateResult::default(),
+        )
+        .unwrap();
+        assert_eq!(chairman.name, "judge");
+        assert!(selection.reason.contains("no aggregated ranking"));
+
+        let (first, _) = select_chairman(
+            ChairmanStrategy::RoundRobin,
+            &configured,
+            &council,
+            &answered,
+            &rankings,
+        )
+        .unwrap();
+        let (second, _) = select_chairman(
+            ChairmanStrategy::RoundRobin,
+            &configured,
+            &council,
+            &answered,
+            &rankings,
+        )
+        .unwrap();
+        assert_ne!(first.name, second.name);
+        assert!(answered.contains(&first.name) && answered.contains(&second.name));
+
+        let (chairman, _) = select_chairman(
+            ChairmanStrategy::External,
+            &configured,
+            &council,
+            &answered,
+            &rankings,
+        )
+        .unwrap();
+        assert_eq!(chairman.name, "judge");
+        let err = select_chairman(
+            ChairmanStrategy::External,
+            &council[0],
+            &council,
+            &answered,
+            &rankings,
+        )
+        .unwrap_err();
+        assert!(err.to_string().contains("invalid request"));
+    }
+}
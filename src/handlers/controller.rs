@@ -1,308 +1,890 @@
-use crate::api::schema::{Document, LLMCouncilRequestSchema, ResponseSummary};
-use crate::config::load::ModelSchema;
+use crate::api::schema::{
+    AggregateResult, AggregationStrategy, ChairmanSelection, ChatMessage, ChatUsage, Document,
+    LLMCouncilRequestSchema, PipelineStage, ProgressEvent, RankingLabels, RankingParse,
+    RankingParseStatus, ResponseObject, ResponseSummary, SelfVoteMode, StageMode,
+};
+use crate::config::load::{ModelSchema, QuorumPolicy};
+use crate::handlers::aggregation::{Ballot, aggregate};
 use crate::handlers::api_calls::*;
+use crate::handlers::breaker::{breaker_allows, record_failure, record_success};
+use crate::handlers::chairman::select_chairman;
+use crate::handlers::conversations::{append_turn, conversation_history};
+use crate::handlers::documents::{document_link, document_name};
+use crate::handlers::generation::member_request;
 use crate::handlers::helper::*;
+use crate::handlers::labels::{assign_judge_labels, default_seed, label_for};
+use crate::handlers::openai::extract_message_content;
+use crate::handlers::pipeline::resolve_pipeline;
+use crate::handlers::prompts::{CHAIRMAN, RANKING, REVISION, get_prompt_version, render_prompt};
+use crate::handlers::queue::RunTicket;
+use crate::handlers::ranking::parse_final_ranking;
+use crate::handlers::runs::*;
 use colored::{ColoredString, Colorize};
 use custom_logger as log;
 use futures::stream::FuturesUnordered;
 use futures::stream::StreamExt;
-use hyper::body::Bytes;
 use regex::Regex;
 use std::collections::BTreeMap;
 
+// waits for the run to be admitted from the queue, executes the flow and records
+// the outcome in the run registry, used for both synchronous and asynchronous requests
+pub async fn run_flow(
+    end_point: String,
+    run_id: String,
+    ticket: RunTicket,
+    req: LLMCouncilRequestSchema,
+) -> Result<ResponseSummary, Box<dyn std::error::Error>> {
+    // the run is marked failed if this future is dropped (or panics) before it finished
+    let guard = RunGuard::new(&run_id);
+    // the permit is held for the duration of the flow and released on drop
+    let permit = ticket.admit().await;
+    log::info!("[run_flow] admitted run {}", permit.id());
+    let result = flow_control(end_point, run_id.clone(), req).await;
+    drop(permit);
+    match result {
+        Ok(summary) => {
+            // when the run can not be completed the guard fails it
+            complete_run(&run_id, summary.clone())?;
+            guard.finish();
+            Ok(summary)
+        }
+        Err(err) => {
+            log::error!("[run_flow] {} {}", run_id, err);
+            let _ = fail_run(&run_id, err.to_string());
+            guard.finish();
+            Err(err)
+        }
+    }
+}
+
+// state shared by the stages of a run
+struct FlowContext {
+    end_point: String,
+    run_id: String,
+    req: LLMCouncilRequestSchema,
+    // the configured council and the members taking part in the run
+    council: Vec<ModelSchema>,
+    cm: Vec<ModelSchema>,
+    quorum: QuorumPolicy,
+    history: Vec<ChatMessage>,
+    seed: u64,
+    self_vote: SelfVoteMode,
+    // members that failed a stage, stage -> (member -> reason)
+    failed_members: BTreeMap<String, BTreeMap<String, String>>,
+    // token usage per stage and member, reused stages report no usage
+    usage: BTreeMap<String, ChatUsage>,
+    // answer
+    initial_responses: BTreeMap<String, String>,
+    initial_merged_responses: String,
+    label_mapping: BTreeMap<String, String>,
+    judge_labels: BTreeMap<String, BTreeMap<String, String>>,
+    // revise, the members that revised their answer in each round
+    revisions: Vec<(String, Vec<String>)>,
+    // rank
+    rankers: Vec<String>,
+    ranking_merged_responses: String,
+    aggregated_rankings: AggregateResult,
+    ranking_validation: BTreeMap<String, RankingParse>,
+    // synthesize
+    chairman: Option<ChairmanSelection>,
+    synthesis: Option<String>,
+}
+
 pub async fn flow_control(
     end_point: String,
-    data: Bytes,
-) -> Result<String, Box<dyn std::error::Error>> {
-    let req: LLMCouncilRequestSchema = serde_json::from_slice(&data)?;
-    let cm = get_council_members()?;
-    if check_semaphore()? {
-        return Err(Box::from("[flow_control] still processing"));
-    } else {
-        let flow_control = req.flow_control;
-
-        // first check the health of all systems
-        log::info!("[flow_control] checking services health");
-        all_health().await?;
-
-        // flow is as follows
-        //
-        // 1. collect initial response from the user prompt to all council members
-        // 2. create a ranking prompt from the responses obtained in step 1
-        // 3. collect the ranking results from all council members using the ranking prompt
-        // 4. create a summary prompt for the council chairman and also calculate rankings score (lower is better)
-        // 5. collect the council chariman's summary
-
-        set_semaphore(true)?;
-
-        // start flow
-        log::info!("[flow_control] triggered flow_control");
-
-        // 1.
-        if (flow_control & 1u8) == 1 {
-            log::info!("[flow_control] executing collect initial responses");
-            collect_initial_responses(
-                end_point.clone(),
-                cm.clone(),
-                req.prompt.clone(),
-                req.title.clone(),
-                req.max_tokens,
-            )
-            .await?;
-            log::info!("[flow_control] completed collect initial responses");
+    run_id: String,
+    req: LLMCouncilRequestSchema,
+) -> Result<ResponseSummary, Box<dyn std::error::Error>> {
+    let (pipeline, stages) = resolve_pipeline(&req)?;
+    let council = get_council_members()?;
+    let mut failed_members: BTreeMap<String, BTreeMap<String, String>> = BTreeMap::new();
+
+    // first check the health of all systems
+    log::info!("[flow_control] checking services health");
+    set_run_stage(&run_id, "health-check")?;
+    let skipped = all_health(run_id.clone()).await?;
+    // members with an open circuit breaker do not take part in the run
+    let cm: Vec<ModelSchema> = council
+        .iter()
+        .filter(|x| !skipped.contains_key(&x.name))
+        .cloned()
+        .collect();
+    if !skipped.is_empty() {
+        failed_members.insert("health".to_string(), skipped);
+    }
+
+    // the stages are executed in the order of the pipeline, the default is
+    //
+    // answer     -> collect the initial response to the user prompt from all council members
+    // rank       -> collect the rankings of the (anonymized) initial responses from all council
+    //               members and aggregate them
+    // synthesize -> collect the council chairman's synthesis
+    //
+    // a reused stage reads the documents stored by an earlier run with the same title
+
+    // start flow
+    log::info!(
+        "[flow_control] triggered flow_control {} pipeline {}",
+        run_id,
+        pipeline
+    );
+
+    // earlier turns of the conversation, replayed to the members and the chairman
+    let history_window = get_history_window()?;
+    let prompt_version = get_prompt_version()?;
+    let conversation = match &req.conversation_id {
+        Some(id) => conversation_history(id, history_window)?,
+        None => vec![],
+    };
+    // the system messages of the request first, then the conversation and the
+    // earlier turns sent with the request
+    let (mut history, turns): (Vec<ChatMessage>, Vec<ChatMessage>) = req
+        .messages
+        .iter()
+        .cloned()
+        .partition(|x| x.role == "system");
+    history.extend(conversation);
+    history.extend(turns);
+    let self_vote = match req.self_vote {
+        Some(mode) => mode,
+        None => get_self_vote_mode()?,
+    };
+    let seed = req.seed.unwrap_or(default_seed(&req.title));
+
+    let mut ctx = FlowContext {
+        end_point,
+        run_id: run_id.clone(),
+        req,
+        council,
+        cm,
+        quorum: get_quorum_policy()?,
+        history,
+        seed,
+        self_vote,
+        failed_members,
+        usage: BTreeMap::new(),
+        initial_responses: BTreeMap::new(),
+        initial_merged_responses: String::new(),
+        label_mapping: BTreeMap::new(),
+        judge_labels: BTreeMap::new(),
+        revisions: vec![],
+        rankers: vec![],
+        ranking_merged_responses: String::new(),
+        aggregated_rankings: AggregateResult::default(),
+        ranking_validation: BTreeMap::new(),
+        chairman: None,
+        synthesis: None,
+    };
+
+    for execution in stages.iter() {
+        if execution.mode == StageMode::Skip {
+            log::info!("[flow_control] skipping stage {}", execution.stage.name());
+            continue;
         }
+        match execution.stage {
+            PipelineStage::Answer => answer_stage(&mut ctx, execution.mode).await?,
+            PipelineStage::Revise => revise_stage(&mut ctx, execution.mode).await?,
+            PipelineStage::Rank => rank_stage(&mut ctx, execution.mode).await?,
+            PipelineStage::Synthesize => synthesize_stage(&mut ctx, execution.mode).await?,
+        }
+    }
+
+    if let (Some(id), Some(synthesis)) = (&ctx.req.conversation_id, &ctx.synthesis) {
+        append_turn(
+            id,
+            ctx.req.prompt.clone(),
+            extract_message_content(synthesis),
+            history_window,
+        )?;
+    }
 
-        // 2.
-        let hm_ir = get_all_documents(cm.clone(), format!("initial-{}", req.title)).await?;
-        let (initial_merged_responses, label_mapping) = format_initial_responses(hm_ir);
-
-        // 3.
-        if (flow_control & 2u8) == 2 {
-            log::info!("[flow_control] executing collect ranking responses");
-            collect_ranking_responses(
-                end_point.clone(),
-                cm.clone(),
-                req.prompt.clone(),
-                req.title.clone(),
-                initial_merged_responses.clone(),
-            )
-            .await?;
-            log::info!("[flow_control] completed collect ranking responses");
+    // only link the documents that exist
+    let title = ctx.req.title.clone();
+    let mut produced = vec![(
+        "initial".to_string(),
+        ctx.initial_responses
+            .keys()
+            .cloned()
+            .collect::<Vec<String>>(),
+    )];
+    produced.extend(ctx.revisions.clone());
+    produced.push(("ranking".to_string(), ctx.rankers.clone()));
+    let mut documents = vec![];
+    for member in ctx.council.iter() {
+        for (stage, members) in produced.iter() {
+            if members.contains(&member.name) {
+                documents.push(document_link(&member.name, stage, &title)?);
+            }
         }
+    }
+    if let (Some(chairman), Some(_)) = (&ctx.chairman, &ctx.synthesis) {
+        documents.push(document_link(&chairman.name, "chairman-summary", &title)?);
+    }
+
+    let mut summary = get_summary(
+        documents,
+        ctx.aggregated_rankings,
+        ctx.ranking_validation,
+        ctx.label_mapping,
+        ctx.usage,
+    )?;
+    summary.seed = ctx.seed;
+    summary.prompt_version = prompt_version;
+    summary.pipeline = pipeline;
+    summary.stages = stages;
+    summary.conversation_id = ctx.req.conversation_id;
+    ctx.failed_members.retain(|_, v| !v.is_empty());
+    summary.failed_members = ctx.failed_members;
+    summary.judge_label_mapping = ctx.judge_labels;
+    summary.chairman = ctx.chairman;
+    let json = serde_json::to_string_pretty(&summary)?;
+    let cs: ColoredString = json.white().bold();
+    log::trace!("[flow_control] {}", cs);
+    log::info!("[flow_control] completed flow {}", run_id);
+    Ok(summary)
+}
+
+// fails a reused stage when an earlier run did not store any documents for it
+fn check_reused(
+    stage: PipelineStage,
+    mode: StageMode,
+    found: usize,
+    title: &str,
+) -> Result<(), Box<dyn std::error::Error>> {
+    if mode == StageMode::Reuse && found == 0 {
+        return Err(Box::from(format!(
+            "[check_reused] stage {} is reused but no documents are stored for title {}",
+            stage.name(),
+            title
+        )));
+    }
+    Ok(())
+}
+
+async fn answer_stage(
+    ctx: &mut FlowContext,
+    mode: StageMode,
+) -> Result<(), Box<dyn std::error::Error>> {
+    set_run_stage(&ctx.run_id, "initial")?;
+    // only the members that answered are read back, for a reused stage all members are tried
+    let mut initial_members = ctx.cm.clone();
+    if mode == StageMode::Run {
+        log::info!("[answer_stage] executing collect initial responses");
+        let outcome = collect_initial_responses(
+            ctx.end_point.clone(),
+            ctx.run_id.clone(),
+            ctx.cm.clone(),
+            &ctx.req,
+            &ctx.history,
+        )
+        .await?;
+        add_usage(&mut ctx.usage, "initial", &outcome.responses);
+        initial_members.retain(|x| !outcome.failed.contains_key(&x.name));
+        ctx.failed_members
+            .insert("initial".to_string(), outcome.failed);
+        log::info!("[answer_stage] completed collect initial responses");
+    }
 
-        // 4.
-        let hm_ranking = get_all_documents(cm.clone(), format!("ranking-{}", req.title)).await?;
-        let ranking_merged_responses = format_ranking_responses(hm_ranking.clone());
-        let aggregated_rankings = calculate_aggregate_rankings(hm_ranking)?;
-
-        // 5.
-        if (flow_control & 4u8) == 4 {
-            log::info!("[flow_control] executing chairman council analysis");
-            chairman_council_analysis(
-                end_point,
-                req.prompt,
-                req.title.clone(),
-                initial_merged_responses,
-                ranking_merged_responses,
-            )
-            .await?;
-            log::info!("[flow_control] completed chairman council analysis");
+    let (hm_ir, missing) = get_all_documents(initial_members, "initial", &ctx.req.title).await?;
+    check_reused(PipelineStage::Answer, mode, hm_ir.len(), &ctx.req.title)?;
+    ctx.failed_members
+        .entry("initial".to_string())
+        .or_default()
+        .extend(missing);
+    check_quorum(
+        "initial",
+        hm_ir.len(),
+        ctx.council.len(),
+        ctx.quorum.initial(ctx.council.len()),
+    )?;
+    let (initial_merged_responses, label_mapping) = format_initial_responses(hm_ir.clone());
+    let members: Vec<String> = hm_ir.keys().cloned().collect();
+    let judges: Vec<String> = ctx.cm.iter().map(|x| x.name.clone()).collect();
+    ctx.judge_labels = assign_judge_labels(
+        &members,
+        &judges,
+        ctx.seed,
+        ctx.self_vote == SelfVoteMode::Omit,
+    );
+    ctx.initial_merged_responses = initial_merged_responses;
+    ctx.label_mapping = label_mapping;
+    ctx.initial_responses = hm_ir;
+    Ok(())
+}
+
+// every round each member revises its latest answer after reading the latest answers
+// of its peers, a member whose revision fails keeps its previous answer
+async fn revise_stage(
+    ctx: &mut FlowContext,
+    mode: StageMode,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let rounds = get_revision_settings()?.rounds;
+    for round in 1..=rounds {
+        let stage = format!("revision-{}", round);
+        set_run_stage(&ctx.run_id, &stage)?;
+        // only the members with an answer revise it
+        let mut revisers: Vec<ModelSchema> = ctx
+            .cm
+            .iter()
+            .filter(|x| ctx.initial_responses.contains_key(&x.name))
+            .cloned()
+            .collect();
+        if mode == StageMode::Run {
+            log::info!(
+                "[revise_stage] executing collect revision responses round {}",
+                round
+            );
+            let outcome = collect_revision_responses(ctx, revisers.clone(), &stage).await?;
+            add_usage(&mut ctx.usage, &stage, &outcome.responses);
+            revisers.retain(|x| !outcome.failed.contains_key(&x.name));
+            ctx.failed_members.insert(stage.clone(), outcome.failed);
+            log::info!(
+                "[revise_stage] completed collect revision responses round {}",
+                round
+            );
         }
+        let (revised, missing) = get_all_documents(revisers, &stage, &ctx.req.title).await?;
+        check_reused(PipelineStage::Revise, mode, revised.len(), &ctx.req.title)?;
+        ctx.failed_members
+            .entry(stage.clone())
+            .or_default()
+            .extend(missing);
+        ctx.revisions
+            .push((stage, revised.keys().cloned().collect()));
+        ctx.initial_responses.extend(revised);
+    }
+    // the ranking and the synthesis use the revised answers, the labels are unchanged
+    // as the members answering are the same
+    let (initial_merged_responses, label_mapping) =
+        format_initial_responses(ctx.initial_responses.clone());
+    ctx.initial_merged_responses = initial_merged_responses;
+    ctx.label_mapping = label_mapping;
+    Ok(())
+}
+
+async fn rank_stage(
+    ctx: &mut FlowContext,
+    mode: StageMode,
+) -> Result<(), Box<dyn std::error::Error>> {
+    set_run_stage(&ctx.run_id, "ranking")?;
+    let mut ranking_members = ctx.cm.clone();
+    if mode == StageMode::Run {
+        log::info!("[rank_stage] executing collect ranking responses");
+        let judge_responses = format_judge_responses(&ctx.initial_responses, &ctx.judge_labels);
+        let outcome = collect_ranking_responses(
+            ctx.end_point.clone(),
+            ctx.run_id.clone(),
+            ctx.cm.clone(),
+            &ctx.req,
+            judge_responses,
+            &ctx.judge_labels,
+        )
+        .await?;
+        add_usage(&mut ctx.usage, "ranking", &outcome.responses);
+        // the rankings are already collected, a later reuse falls back to the run seed
+        if let Err(e) = store_ranking_labels(&ctx.req.title, ctx.seed, &ctx.judge_labels).await {
+            log::error!("[rank_stage] ranking labels not stored {}", e);
+            ctx.failed_members.insert(
+                RANKING_LABELS_STAGE.to_string(),
+                BTreeMap::from([(RANKING_LABELS_OWNER.to_string(), e.to_string())]),
+            );
+        }
+        ranking_members.retain(|x| !outcome.failed.contains_key(&x.name));
+        ctx.failed_members
+            .insert("ranking".to_string(), outcome.failed);
+        log::info!("[rank_stage] completed collect ranking responses");
+    }
 
-        let summary = get_summary(req.title, aggregated_rankings, label_mapping)?;
-        let json = serde_json::to_string_pretty(&summary)?;
-        let cs: ColoredString = json.white().bold();
-        log::trace!("[flow_control] {}", cs);
-        log::info!("[flow_control] completed flow");
-        // all good set semaphore to false
-        set_semaphore(false)?;
-        Ok(json)
+    if mode == StageMode::Reuse {
+        // the labels are derived from the seed of the earlier run, which may differ
+        // from the seed of this run
+        match read_ranking_labels(&ctx.req.title).await {
+            Ok(labels) => {
+                ctx.seed = labels.seed;
+                ctx.judge_labels = labels.judge_labels;
+            }
+            Err(e) => log::info!(
+                "[rank_stage] no stored ranking labels, using seed {} {}",
+                ctx.seed,
+                e
+            ),
+        }
     }
+
+    set_run_stage(&ctx.run_id, "aggregate")?;
+    let (hm_ranking, missing) =
+        get_all_documents(ranking_members, "ranking", &ctx.req.title).await?;
+    check_reused(PipelineStage::Rank, mode, hm_ranking.len(), &ctx.req.title)?;
+    ctx.failed_members
+        .entry("ranking".to_string())
+        .or_default()
+        .extend(missing);
+    check_quorum(
+        "ranking",
+        hm_ranking.len(),
+        ctx.council.len(),
+        ctx.quorum.ranking(),
+    )?;
+    ctx.ranking_merged_responses =
+        format_ranking_responses(hm_ranking.clone(), &ctx.judge_labels, &ctx.label_mapping);
+    let strategy = match ctx.req.aggregation {
+        Some(strategy) => strategy,
+        None => get_aggregation_strategy()?,
+    };
+    let members: Vec<String> = ctx.initial_responses.keys().cloned().collect();
+    ctx.rankers = hm_ranking.keys().cloned().collect();
+    let weights: BTreeMap<String, f64> = ctx
+        .council
+        .iter()
+        .map(|x| (x.name.clone(), x.vote_weight()))
+        .collect();
+    let (aggregated_rankings, ranking_validation) = calculate_aggregate_rankings(
+        hm_ranking,
+        &ctx.judge_labels,
+        &members,
+        &weights,
+        ctx.self_vote,
+        strategy,
+    );
+    publish_event(
+        ProgressEvent::new("aggregate", &ctx.run_id)
+            .with_data(serde_json::to_value(&aggregated_rankings)?),
+    );
+    ctx.aggregated_rankings = aggregated_rankings;
+    ctx.ranking_validation = ranking_validation;
+    Ok(())
+}
+
+// the ranking labels are stored under the council itself (not a member)
+const RANKING_LABELS_OWNER: &str = "council";
+const RANKING_LABELS_STAGE: &str = "ranking-labels";
+
+async fn store_ranking_labels(
+    title: &str,
+    seed: u64,
+    judge_labels: &BTreeMap<String, BTreeMap<String, String>>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let labels = RankingLabels {
+        seed,
+        judge_labels: judge_labels.clone(),
+    };
+    let document = document_name(RANKING_LABELS_OWNER, RANKING_LABELS_STAGE, title)?;
+    let content = serde_json::to_string_pretty(&labels)?;
+    let store = get_document_store()?;
+    store
+        .write(&document, &content)
+        .await
+        .map_err(|e| format!("[store_ranking_labels] {} {}", document, e))?;
+    Ok(())
+}
+
+async fn read_ranking_labels(title: &str) -> Result<RankingLabels, Box<dyn std::error::Error>> {
+    let content = get_document(RANKING_LABELS_OWNER, RANKING_LABELS_STAGE, title).await?;
+    let labels: RankingLabels =
+        serde_json::from_str(&content).map_err(|e| format!("[read_ranking_labels] {}", e))?;
+    Ok(labels)
 }
 
-pub async fn all_health() -> Result<(), Box<dyn std::error::Error>> {
-    let doc_url = get_document_store_url()?;
-    let res_doc = process_get_call(format!("{}/v1/health", doc_url)).await?;
-    log::info!("[all_health] document-service {}", res_doc);
+async fn synthesize_stage(
+    ctx: &mut FlowContext,
+    mode: StageMode,
+) -> Result<(), Box<dyn std::error::Error>> {
+    set_run_stage(&ctx.run_id, "chairman")?;
+    let strategy = match ctx.req.chairman_strategy {
+        Some(strategy) => strategy,
+        None => get_chairman_strategy()?,
+    };
+    let configured = get_council_chairman()?;
+    let answered: Vec<String> = ctx.initial_responses.keys().cloned().collect();
+    let (mut chairman, mut selection) = select_chairman(
+        strategy,
+        &configured,
+        &ctx.council,
+        &answered,
+        &ctx.aggregated_rankings,
+    )?;
+    log::info!(
+        "[synthesize_stage] chairman {} selected ({})",
+        chairman.name,
+        selection.reason
+    );
+    if mode == StageMode::Run {
+        log::info!("[synthesize_stage] executing chairman council analysis");
+        let response = chairman_council_analysis(
+            ctx.end_point.clone(),
+            &chairman,
+            &ctx.req,
+            ctx.initial_merged_responses.clone(),
+            ctx.ranking_merged_responses.clone(),
+            format_trust(&ctx.council, &ctx.label_mapping),
+            &ctx.history,
+        )
+        .await?;
+        add_usage(&mut ctx.usage, "chairman", &[response]);
+        log::info!("[synthesize_stage] completed chairman council analysis");
+    } else {
+        // the reused synthesis was stored by the chairman of the earlier run, which can
+        // differ from the chairman selected now (e.g. round-robin)
+        let selected_stored = get_document(&chairman.name, "chairman-summary", &ctx.req.title)
+            .await
+            .is_ok();
+        if !selected_stored {
+            let mut stored = vec![configured];
+            stored.extend(ctx.council.iter().cloned());
+            for candidate in stored.into_iter() {
+                let found = get_document(&candidate.name, "chairman-summary", &ctx.req.title)
+                    .await
+                    .is_ok();
+                if found {
+                    selection.name = candidate.name.clone();
+                    selection.model = candidate.model.clone();
+                    selection.reason = format!("synthesis reused from chairman {}", candidate.name);
+                    chairman = candidate;
+                    break;
+                }
+            }
+        }
+    }
+    let synthesis = match get_document(&chairman.name, "chairman-summary", &ctx.req.title).await {
+        Ok(synthesis) => synthesis,
+        Err(e) => {
+            check_reused(PipelineStage::Synthesize, mode, 0, &ctx.req.title)?;
+            return Err(e);
+        }
+    };
+    publish_event(
+        ProgressEvent::new("chairman", &ctx.run_id)
+            .with_member(&chairman.name, "completed".to_string())
+            .with_data(serde_json::json!(synthesis)),
+    );
+    ctx.chairman = Some(selection);
+    ctx.synthesis = Some(synthesis);
+    Ok(())
+}
+
+// checks the health of the document-service and the council members, returns the
+// members skipped for this run (member -> reason) as their circuit breaker is open
+pub async fn all_health(
+    run_id: String,
+) -> Result<BTreeMap<String, String>, Box<dyn std::error::Error>> {
+    let store = get_document_store()?;
+    let res_doc = store.health().await?;
+    log::info!("[all_health] document-store {}", res_doc);
     let council_members = get_council_members()?;
     let cm = council_members.clone();
+    let mut skipped: BTreeMap<String, String> = BTreeMap::new();
     for ms in cm.iter() {
-        let response = process_get_call(format!("{}/v1/health", ms.url)).await;
+        if !breaker_allows(&ms.name)? {
+            log::info!("[all_health] {} skipped circuit open", ms.name);
+            set_member_status(&run_id, "health", &ms.name, "circuit open".to_string())?;
+            skipped.insert(ms.name.clone(), "circuit open".to_string());
+            continue;
+        }
+        let timeout = get_call_timeout(ms, "health")?;
+        let response = process_get_call(format!("{}/v1/health", ms.url), timeout).await;
         match response {
             Ok(content) => {
                 log::info!("[all_health] {} {}", ms.name, content.replace("\n", ""));
+                set_member_status(&run_id, "health", &ms.name, "ok".to_string())?;
             }
             Err(e) => {
                 log::error!("[all_health] {} {}", ms.name, e);
+                set_member_status(&run_id, "health", &ms.name, format!("error {}", e))?;
+                record_failure(&ms.name, format!("health {}", e))?;
+                // skip the member if this failure opened the breaker
+                if !breaker_allows(&ms.name)? {
+                    skipped.insert(ms.name.clone(), format!("circuit open {}", e));
+                }
             }
         }
     }
-    Ok(())
+    Ok(skipped)
 }
 
 async fn collect_initial_responses(
     end_point: String,
+    run_id: String,
     council_members: Vec<ModelSchema>,
-    prompt: String,
-    title: String,
-    max_tokens: usize,
-) -> Result<(), Box<dyn std::error::Error>> {
+    req: &LLMCouncilRequestSchema,
+    history: &[ChatMessage],
+) -> Result<StageOutcome, Box<dyn std::error::Error>> {
     let cm = council_members.clone();
-    let doc_url = get_document_store_url()?;
-    let mut futs = FuturesUnordered::new();
+    let futs = FuturesUnordered::new();
     // call all services in parallel
     for ms in cm.iter() {
         let name = ms.name.clone();
         let url = ms.url.clone();
-        let message = format!(
-            r##"{{ "model": "{}", "messages": [{{"role": "user", "content": "{}" }}], "max_tokens": {} }}"##,
-            ms.model, prompt, max_tokens
-        );
+        let message = member_request(ms, "initial", req, history, req.prompt.clone());
         let updated_url = format!("{}{}", url, end_point);
-        let updated_title = format!("initial-{}", title);
-        futs.push(process_post_call(
-            name,
-            updated_url,
-            doc_url.clone(),
-            updated_title,
-            message.clone(),
-        ));
+        let document = document_name(&name, "initial", &req.title)?;
+        let timeout = get_call_timeout(ms, "initial")?;
+        futs.push(async move {
+            let response =
+                process_post_call(name.clone(), updated_url, document, message, timeout).await;
+            (name, response)
+        });
     }
     // wait for all posts to complete
-    while let Some(response) = futs.next().await {
-        match response {
-            Ok(contents) => {
-                log::info!(
-                    "[collect_initial_responses] {}  {}",
-                    contents.process_name,
-                    contents.status_code
-                )
-            }
-            Err(e) => {
-                return Err(Box::from(format!("[collect_initial_responses] {}", e)));
-            }
-        }
+    await_stage_responses("initial", &run_id, futs).await
+}
+
+// the members revise the latest answers in ctx.initial_responses
+async fn collect_revision_responses(
+    ctx: &FlowContext,
+    council_members: Vec<ModelSchema>,
+    stage: &str,
+) -> Result<StageOutcome, Box<dyn std::error::Error>> {
+    let answers = &ctx.initial_responses;
+    let futs = FuturesUnordered::new();
+    // call all services in parallel
+    for ms in council_members.iter() {
+        let updated_url = format!("{}{}", ms.url, ctx.end_point);
+        let document = document_name(&ms.name, stage, &ctx.req.title)?;
+        let answer = answers.get(&ms.name).cloned().unwrap_or_default();
+        // the anonymized answers of the peers, without the member's own answer
+        let mut peers = answers.clone();
+        peers.remove(&ms.name);
+        let (responses_merged, _) = format_initial_responses(peers);
+        let stage_prompt = render_prompt(
+            REVISION,
+            &ms.name,
+            &[
+                ("question", &ctx.req.prompt),
+                ("answer", &answer),
+                ("responses", &responses_merged),
+            ],
+        )?;
+        let message = member_request(ms, "revision", &ctx.req, &[], stage_prompt);
+        let name = ms.name.clone();
+        let timeout = get_call_timeout(ms, "revision")?;
+        futs.push(async move {
+            let response =
+                process_post_call(name.clone(), updated_url, document, message, timeout).await;
+            (name, response)
+        });
     }
-    Ok(())
+    // wait for all posts to complete
+    await_stage_responses(stage, &ctx.run_id, futs).await
 }
 
 async fn collect_ranking_responses(
     end_point: String,
+    run_id: String,
     council_members: Vec<ModelSchema>,
-    prompt: String,
-    title: String,
-    initial_responses_merged: String,
-) -> Result<(), Box<dyn std::error::Error>> {
-    let mut futs = FuturesUnordered::new();
-    let mut stage_prompt = format!(
-        r##"
-        You are evaluating different responses to the following question:
-
-        Question {:?} 
-            
-        Here are the responses from different models (anonymized):
-        
-        {:?} 
-    "##,
-        prompt, initial_responses_merged
-    );
-
-    stage_prompt.push_str(
-        r#"
-        
-        Your task:
-        1. First, evaluate each response individually. For each response, explain what it does well and what it does poorly.
-        2. Then, at the very end of your response, provide a final ranking.
-
-        IMPORTANT: Your final ranking MUST be formatted EXACTLY as follows:
-        - Start with the line "FINAL RANKING:" (all caps, with colon)
-        - Then list the responses from best to worst as a numbered list
-        - Each line should be: number, period, space, then ONLY the response label (e.g., "1. Response A")
-        - Do not add any other text or explanations in the ranking section
-
-        Example of the correct format for your ENTIRE response:
-
-        Response A provides good detail on X but misses Y...
-        Response B is accurate but lacks depth on Z...
-        Response C offers the most comprehensive answer...
-
-        FINAL RANKING:
-        1. Response C
-        2. Response A
-        3. Response B
-
-        Now provide your evaluation and ranking:
-        "#,
-        );
-
-    let doc_url = get_document_store_url()?;
+    req: &LLMCouncilRequestSchema,
+    judge_responses: BTreeMap<String, String>,
+    judge_labels: &BTreeMap<String, BTreeMap<String, String>>,
+) -> Result<StageOutcome, Box<dyn std::error::Error>> {
+    let futs = FuturesUnordered::new();
     // call all services in parallel
     for ms in council_members.clone().iter() {
         let updated_url = format!("{}{}", ms.url, end_point.clone());
-        let updated_title = format!("ranking-{}", title);
-        let message = format!(
-            r##"{{ "model": "{}", "messages": [{{"role": "user", "content": {:?} }}], "max_tokens": {} }}"##,
-            ms.model, stage_prompt, 16384
-        );
-        futs.push(process_post_call(
-            ms.name.clone(),
-            updated_url,
-            doc_url.clone(),
-            updated_title.clone(),
-            message.clone(),
-        ));
+        let document = document_name(&ms.name, "ranking", &req.title)?;
+        let responses_merged = judge_responses.get(&ms.name).cloned().unwrap_or_default();
+        let labels = judge_labels
+            .get(&ms.name)
+            .map(|x| x.keys().cloned().collect::<Vec<String>>().join(", "))
+            .unwrap_or_default();
+        let stage_prompt = render_prompt(
+            RANKING,
+            &ms.name,
+            &[
+                ("question", &req.prompt),
+                ("responses", &responses_merged),
+                ("labels", &labels),
+            ],
+        )?;
+        let message = member_request(ms, "ranking", req, &[], stage_prompt);
+        let name = ms.name.clone();
+        let timeout = get_call_timeout(ms, "ranking")?;
+        futs.push(async move {
+            let response =
+                process_post_call(name.clone(), updated_url, document, message, timeout).await;
+            (name, response)
+        });
     }
     // wait for all posts to complete
-    while let Some(response) = futs.next().await {
+    await_stage_responses("ranking", &run_id, futs).await
+}
+
+// waits for all member calls of a stage, a member fails when the call errors or
+// when the member (or the document-service) does not return 200, failures are
+// collected so the flow can continue with the members that answered, only the
+// outcome of the member call counts for its circuit breaker
+async fn await_stage_responses<F>(
+    stage: &str,
+    run_id: &str,
+    mut futs: FuturesUnordered<F>,
+) -> Result<StageOutcome, Box<dyn std::error::Error>>
+where
+    F: Future<Output = (String, Result<ResponseObject, Box<dyn std::error::Error>>)>,
+{
+    let mut outcome = StageOutcome::default();
+    while let Some((name, response)) = futs.next().await {
         match response {
-            Ok(contents) => {
+            Ok(contents) if contents.status_code == 200 => {
                 log::info!(
-                    "[collect_ranking_responses] {}  {}",
+                    "[await_stage_responses] {} {}  {}",
+                    stage,
                     contents.process_name,
                     contents.status_code
-                )
+                );
+                set_member_status(run_id, stage, &name, member_status(&contents))?;
+                record_success(&name)?;
+                outcome.responses.push(contents);
+            }
+            Ok(contents) => {
+                log::error!(
+                    "[await_stage_responses] {} {} {} {}",
+                    stage,
+                    name,
+                    contents.status_code,
+                    contents.contents
+                );
+                set_member_status(run_id, stage, &name, contents.status_code.to_string())?;
+                record_failure(&name, format!("{} status {}", stage, contents.status_code))?;
+                outcome.failed.insert(
+                    name,
+                    format!(
+                        "status {} {}",
+                        contents.status_code,
+                        contents.contents.trim()
+                    ),
+                );
+            }
+            Err(e) if e.is::<StoreError>() => {
+                log::error!("[await_stage_responses] {} {} {}", stage, name, e);
+                set_member_status(run_id, stage, &name, format!("error {}", e))?;
+                // the member answered, only the document store failed
+                record_success(&name)?;
+                outcome.failed.insert(name, e.to_string());
             }
             Err(e) => {
-                return Err(Box::from(format!("[collect_ranking_responses] {}", e)));
+                log::error!("[await_stage_responses] {} {} {}", stage, name, e);
+                set_member_status(run_id, stage, &name, format!("error {}", e))?;
+                record_failure(&name, format!("{} {}", stage, e))?;
+                outcome.failed.insert(name, e.to_string());
             }
         }
     }
+    Ok(outcome)
+}
+
+// fails the flow when fewer than the required number of members answered a stage
+fn check_quorum(
+    stage: &str,
+    answered: usize,
+    total: usize,
+    required: usize,
+) -> Result<(), Box<dyn std::error::Error>> {
+    if answered < required {
+        return Err(Box::from(format!(
+            "[check_quorum] {} quorum not met {} of {} members answered (required {})",
+            stage, answered, total, required
+        )));
+    }
+    log::info!(
+        "[check_quorum] {} {} of {} members answered (required {})",
+        stage,
+        answered,
+        total,
+        required
+    );
     Ok(())
 }
 
 async fn chairman_council_analysis(
     end_point: String,
-    prompt: String,
-    title: String,
+    chairman: &ModelSchema,
+    req: &LLMCouncilRequestSchema,
     initial_responses_merged: String,
     ranking_responses_merged: String,
-) -> Result<(), Box<dyn std::error::Error>> {
-    let stage_prompt = format!(
-        r##"
-        You are the Chairman of an LLM Council. Multiple AI models have provided responses to a user's question, and then ranked each other's responses.
-
-        Original Question {:?} 
-
-        STAGE 1 - Individual Responses:
-        {:?}
-
-        STAGE 2 - Peer Rankings:
-        {:?}
-
-        Your task as Chairman is to synthesize all of this information into a single, comprehensive, accurate answer to the user's original question. Consider:
-        - The individual responses and their insights
-        - The peer rankings and what they reveal about response quality
-        - Any patterns of agreement or disagreement
-
-        Provide a clear, well-reasoned final answer that represents the council's collective wisdom:
-
-        "##,
-        prompt, initial_responses_merged, ranking_responses_merged
-    );
-
-    let chairman = get_council_chairman()?;
-    let chairman_url = chairman.url;
-    let updated_url = format!("{}{}", chairman_url, end_point);
-    let doc_url = get_document_store_url()?;
-    let updated_title = format!("chairman-summary-{}", title);
-    let message = format!(
-        r##"{{ "model": "{}", "messages": [{{"role": "user", "content": {:?} }}] }}"##,
-        chairman.model, stage_prompt
-    );
+    trust: String,
+    history: &[ChatMessage],
+) -> Result<ResponseObject, Box<dyn std::error::Error>> {
+    let stage_prompt = render_prompt(
+        CHAIRMAN,
+        &chairman.name,
+        &[
+            ("question", &req.prompt),
+            ("responses", &initial_responses_merged),
+            ("rankings", &ranking_responses_merged),
+            ("trust", &trust),
+        ],
+    )?;
+    let timeout = get_call_timeout(chairman, "chairman")?;
+    let message = member_request(chairman, "chairman", req, history, stage_prompt);
+    let updated_url = format!("{}{}", chairman.url, end_point);
+    let document = document_name(&chairman.name, "chairman-summary", &req.title)?;
     let response = process_post_call(
         chairman.name.clone(),
         updated_url,
-        doc_url,
-        updated_title,
+        document,
         message,
+        timeout,
     )
-    .await?;
+    .await;
+    let response = match response {
+        Ok(response) => response,
+        Err(e) if e.is::<StoreError>() => {
+            record_success(&chairman.name)?;
+            return Err(e);
+        }
+        Err(e) => {
+            record_failure(&chairman.name, format!("chairman {}", e))?;
+            return Err(e);
+        }
+    };
     match response.status_code {
-        200 => Ok(()),
-        _ => Err(Box::from(format!(
-            "[council_analysis] {} : {}",
-            chairman.name, response.contents
-        ))),
+        200 => {
+            record_success(&chairman.name)?;
+            Ok(response)
+        }
+        _ => {
+            record_failure(
+                &chairman.name,
+                format!("chairman status {}", response.status_code),
+            )?;
+            Err(Box::from(format!(
+                "[council_analysis] {} : {}",
+                chairman.name, response.contents
+            )))
+        }
+    }
+}
+
+// status reported for a member call, includes the finish reason of the completion
+fn member_status(response: &ResponseObject) -> String {
+    match response
+        .reply
+        .as_ref()
+        .and_then(|x| x.finish_reason.clone())
+    {
+        Some(reason) => {
+            if reason == "length" {
+                log::info!(
+                    "[member_status] {} response truncated (max tokens reached)",
+                    response.process_name
+                );
+            }
+            format!("{} {}", response.status_code, reason)
+        }
+        None => response.status_code.to_string(),
+    }
+}
+
+// the responses and failures of the members for a stage
+#[derive(Debug, Default)]
+struct StageOutcome {
+    responses: Vec<ResponseObject>,
+    failed: BTreeMap<String, String>,
+}
+
+fn add_usage(usage: &mut BTreeMap<String, ChatUsage>, stage: &str, responses: &[ResponseObject]) {
+    for response in responses.iter() {
+        if let Some(member_usage) = response.reply.as_ref().and_then(|x| x.usage.clone()) {
+            usage.insert(format!("{}-{}", stage, response.process_name), member_usage);
+        }
     }
 }
 
@@ -312,7 +894,7 @@ fn format_initial_responses(
     let mut stage_prompt = String::new();
     let mut label_model: BTreeMap<String, String> = BTreeMap::new();
     for (count, (k, v)) in initial_responses.clone().iter().enumerate() {
-        let label = format!("Response {}", (65 + count as u8) as char);
+        let label = label_for(count);
         let model_response = format!(r#"{}:{}"#, label, v);
         label_model.insert(k.to_owned(), label);
         stage_prompt.push_str(&model_response);
@@ -320,76 +902,146 @@ fn format_initial_responses(
     (stage_prompt, label_model)
 }
 
-fn format_ranking_responses(ranking_responses: BTreeMap<String, String>) -> String {
+// the trust the chairman should put in each (anonymized) response, empty when
+// all members are fully trusted
+fn format_trust(council: &[ModelSchema], label_mapping: &BTreeMap<String, String>) -> String {
+    if council.iter().all(|x| x.trust.is_none()) {
+        return String::new();
+    }
+    let mut trust: Vec<(String, f64)> = council
+        .iter()
+        .filter_map(|x| {
+            label_mapping
+                .get(&x.name)
+                .map(|label| (label.clone(), x.answer_trust()))
+        })
+        .collect();
+    trust.sort_by(|a, b| a.0.cmp(&b.0));
+    let mut stage_prompt = String::from(
+        "Trust in the responses (from 0 to 1, weigh the less trusted responses with more scepticism):\n",
+    );
+    for (label, value) in trust.iter() {
+        stage_prompt.push_str(&format!("{}: {}\n", label, value));
+    }
+    stage_prompt
+}
+
+// the anonymized responses each judge evaluates, in the judge's own
+// (shuffled) order and labelling
+fn format_judge_responses(
+    initial_responses: &BTreeMap<String, String>,
+    judge_labels: &BTreeMap<String, BTreeMap<String, String>>,
+) -> BTreeMap<String, String> {
+    let mut judge_responses: BTreeMap<String, String> = BTreeMap::new();
+    for (judge, mapping) in judge_labels.iter() {
+        let mut stage_prompt = String::new();
+        for (label, member) in mapping.iter() {
+            let v = initial_responses.get(member).cloned().unwrap_or_default();
+            stage_prompt.push_str(&format!(r#"{}:{}"#, label, v));
+        }
+        judge_responses.insert(judge.clone(), stage_prompt);
+    }
+    judge_responses
+}
+
+// merges the rankings for the chairman, each judge used its own labels so the
+// labels are translated to the labels of the merged initial responses first
+fn format_ranking_responses(
+    ranking_responses: BTreeMap<String, String>,
+    judge_labels: &BTreeMap<String, BTreeMap<String, String>>,
+    label_mapping: &BTreeMap<String, String>,
+) -> String {
+    let re = Regex::new(r"Response [A-Z]+\b").expect("valid regex");
     let mut stage_prompt = String::new();
-    for (count, (_k, v)) in ranking_responses.clone().iter().enumerate() {
-        let label = format!("Response {}", (65 + count as u8) as char);
-        let model_response = format!(r#"{}:{}"#, label, v);
+    for (count, (k, v)) in ranking_responses.iter().enumerate() {
+        let translated = match judge_labels.get(k) {
+            Some(mapping) => re.replace_all(v, |caps: &regex::Captures| {
+                let label = &caps[0];
+                mapping
+                    .get(label)
+                    .and_then(|member| label_mapping.get(member))
+                    .cloned()
+                    .unwrap_or(label.to_string())
+            }),
+            None => std::borrow::Cow::from(v),
+        };
+        let model_response = format!(r#"Ranking {}:{}"#, count + 1, translated);
         stage_prompt.push_str(&model_response);
     }
     stage_prompt
 }
 
+// parses the FINAL RANKING section of each judge and aggregates the valid and partial
+// rankings with the selected strategy, invalid rankings are excluded,
+// the parse result of each judge is returned for the summary
+//
+// each judge ranked its own labels, the rankings are translated back to the members
+// (using the judge's label mapping) before aggregation, so the candidates are members
+//
+// unless self votes are included the judge itself is excluded from its ballot,
+// in omit mode the judge never saw its own response
 fn calculate_aggregate_rankings(
     hm_ir: BTreeMap<String, String>,
-) -> Result<BTreeMap<String, usize>, Box<dyn std::error::Error>> {
-    let mut hm_result: BTreeMap<String, usize> = BTreeMap::new();
-    let re = Regex::new(r"\d+\.\s*Response [A-Z]")?;
-    for v in hm_ir.values() {
-        let all = re.captures_iter(v);
-        for (count, ranking) in all.enumerate() {
-            let key = ranking
-                .get_match()
-                .as_str()
-                .split(". ")
-                .nth(1)
-                .unwrap_or("");
-            let val = hm_result.get(key).unwrap_or(&0);
-            hm_result.insert(key.to_string(), count + val);
+    judge_labels: &BTreeMap<String, BTreeMap<String, String>>,
+    members: &[String],
+    weights: &BTreeMap<String, f64>,
+    self_vote: SelfVoteMode,
+    strategy: AggregationStrategy,
+) -> (AggregateResult, BTreeMap<String, RankingParse>) {
+    let mut ballots: Vec<Ballot> = vec![];
+    let mut hm_parse: BTreeMap<String, RankingParse> = BTreeMap::new();
+    for (judge, v) in hm_ir.iter() {
+        let mapping = judge_labels.get(judge).cloned().unwrap_or_default();
+        let excluded: Vec<String> = match self_vote {
+            SelfVoteMode::Include => vec![],
+            _ => members.iter().filter(|x| *x == judge).cloned().collect(),
+        };
+        let labels: Vec<String> = mapping.keys().cloned().collect();
+        let parse = parse_final_ranking(v, &labels);
+        match parse.status {
+            RankingParseStatus::Invalid => {
+                log::error!(
+                    "[calculate_aggregate_rankings] {} ranking excluded {}",
+                    judge,
+                    parse.reason.clone().unwrap_or_default()
+                );
+            }
+            _ => {
+                ballots.push(Ballot {
+                    judge: judge.to_owned(),
+                    ranking: parse
+                        .ranking
+                        .iter()
+                        .filter_map(|x| mapping.get(x))
+                        .filter(|x| !excluded.contains(x))
+                        .cloned()
+                        .collect(),
+                    excluded,
+                    weight: weights.get(judge).copied().unwrap_or(1.0),
+                });
+            }
         }
+        hm_parse.insert(judge.to_owned(), parse);
     }
-    Ok(hm_result)
+    let mut result = aggregate(strategy, &ballots, members);
+    result.self_vote = self_vote;
+    (result, hm_parse)
 }
 
 fn get_summary(
-    title: String,
-    rankings: BTreeMap<String, usize>,
+    documents: Vec<Document>,
+    rankings: AggregateResult,
+    validation: BTreeMap<String, RankingParse>,
     mapping: BTreeMap<String, String>,
+    usage: BTreeMap<String, ChatUsage>,
 ) -> Result<ResponseSummary, Box<dyn std::error::Error>> {
-    let cm = get_council_members()?;
-    let mut vec_documents = vec![];
-    for member in cm.clone().iter() {
-        let doc_initial = Document {
-            name: member.name.clone(),
-            url: format!(
-                "https://unikernel-sandbox/document-store/read?document={}-initial-{}.md",
-                member.name, title
-            ),
-        };
-        let doc_ranking = Document {
-            name: member.name.clone(),
-            url: format!(
-                "https://unikernel-sandbox/document-store/read?document={}-ranking-{}.md",
-                member.name, title
-            ),
-        };
-        vec_documents.push(doc_initial);
-        vec_documents.push(doc_ranking);
-    }
-    let chairman = get_council_chairman()?;
-    let doc_chairman = Document {
-        name: chairman.name.clone(),
-        url: format!(
-            "https://unikernel-sandbox/document-store/read?document={}-chairman-summary-{}.md",
-            chairman.name, title
-        ),
-    };
-    vec_documents.push(doc_chairman);
-
     let summary = ResponseSummary {
-        documents: vec_documents.clone(),
+        documents,
         summary_result: rankings,
         response_mapping: mapping,
+        ranking_validation: validation,
+        usage,
+        ..Default::default()
     };
     Ok(summary)
 }
@@ -469,4 +1121,86 @@ mod tests {
         }
         println!("result {:?}", bt_result);
     }
+
+    #[test]
+    fn test_format_trust() {
+        let member = |name: &str, trust: Option<f64>| super::ModelSchema {
+            trust,
+            ..crate::test_support::member(1, name)
+        };
+        let labels = BTreeMap::from([
+            ("gemini".to_string(), "Response B".to_string()),
+            ("grok".to_string(), "Response A".to_string()),
+        ]);
+        let council = vec![member("gemini", None), member("grok", None)];
+        assert_eq!(super::format_trust(&council, &labels), "");
+        let council = vec![member("gemini", None), member("grok", Some(0.5))];
+        let trust = super::format_trust(&council, &labels);
+        assert!(trust.ends_with("Response A: 0.5\nResponse B: 1\n"));
+    }
+
+    #[tokio::test]
+    async fn test_ranking_labels_round_trip() {
+        use crate::handlers::reload::with_run_config;
+        use crate::test_support::{run_config, testing_parameters};
+
+        let config = run_config(testing_parameters());
+        // a ranking stored with an explicit seed is read back with that seed's labels
+        let judge_labels = BTreeMap::from([(
+            "grok".to_string(),
+            BTreeMap::from([
+                ("Response A".to_string(), "gemini".to_string()),
+                ("Response B".to_string(), "grok".to_string()),
+            ]),
+        )]);
+        let labels = with_run_config(config, async {
+            assert!(super::read_ranking_labels("labels").await.is_err());
+            super::store_ranking_labels("labels", 42, &judge_labels)
+                .await
+                .unwrap();
+            super::read_ranking_labels("labels").await.unwrap()
+        })
+        .await;
+        assert_eq!(labels.seed, 42);
+        assert_eq!(labels.judge_labels, judge_labels);
+    }
+
+    #[tokio::test]
+    async fn test_store_failure_keeps_breaker_closed() {
+        use crate::BREAKERS;
+        use crate::api::schema::{BreakerState, ResponseObject};
+        use crate::config::load::BreakerPolicy;
+        use crate::handlers::api_calls::StoreError;
+        use crate::handlers::runs::{create_run, next_run_id};
+        use futures::stream::FuturesUnordered;
+        use std::time::Instant;
+
+        let policy = BreakerPolicy::default();
+        let member = "store-outage-member".to_string();
+        BREAKERS
+            .lock()
+            .unwrap()
+            .entry(member.clone())
+            .or_default()
+            .on_failure(&policy, "timeout".to_string(), Instant::now());
+        let run_id = next_run_id().unwrap();
+        create_run(&run_id, "store outage".to_string(), vec![member.clone()]).unwrap();
+
+        // the member answered but the document write failed
+        let futs = FuturesUnordered::new();
+        let name = member.clone();
+        futs.push(async move {
+            let response: Result<ResponseObject, Box<dyn std::error::Error>> = Err(Box::new(
+                StoreError("document write status 503".to_string()),
+            ));
+            (name, response)
+        });
+        let outcome = super::await_stage_responses("initial", &run_id, futs)
+            .await
+            .unwrap();
+        assert!(outcome.failed.contains_key(&member));
+        let status = BREAKERS.lock().unwrap()[&member].status(&policy, Instant::now());
+        assert_eq!(status.state, BreakerState::Closed);
+        assert_eq!(status.consecutive_failures, 0);
+    }
 }
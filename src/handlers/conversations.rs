@@ -0,0 +1,97 @@
+use crate::CONVERSATIONS;
+use crate::api::schema::ChatMessage;
+use std::collections::BTreeMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+// conversation history - every completed run with a conversation id appends the
+// user prompt and the chairman's synthesis, follow-up runs replay the latest turns
+// (history_window) to the members in stage 1 and to the chairman
+//
+// the history is kept in memory, only the turns within the window are retained
+
+// least recently updated conversations are pruned once this limit is reached
+const MAX_RETAINED_CONVERSATIONS: usize = 256;
+
+static CONVERSATION_CLOCK: AtomicU64 = AtomicU64::new(0);
+
+#[derive(Debug, Clone, Default)]
+pub struct Conversation {
+    updated: u64,
+    messages: Vec<ChatMessage>,
+}
+
+// the latest turns of the conversation as chat messages (oldest first)
+pub fn conversation_history(
+    id: &str,
+    window: usize,
+) -> Result<Vec<ChatMessage>, Box<dyn std::error::Error>> {
+    let conversations = CONVERSATIONS.lock().map_err(|_| "mutex lock failed")?;
+    let history = match conversations.get(id) {
+        Some(conversation) => {
+            let keep = conversation.messages.len().min(window * 2);
+            conversation.messages[conversation.messages.len() - keep..].to_vec()
+        }
+        None => vec![],
+    };
+    Ok(history)
+}
+
+pub fn append_turn(
+    id: &str,
+    prompt: String,
+    synthesis: String,
+    window: usize,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let mut conversations = CONVERSATIONS.lock().map_err(|_| "mutex lock failed")?;
+    if !conversations.contains_key(id) {
+        prune_conversations(&mut conversations);
+    }
+    let conversation = conversations.entry(id.to_string()).or_default();
+    conversation.updated = CONVERSATION_CLOCK.fetch_add(1, Ordering::SeqCst);
+    conversation.messages.push(ChatMessage {
+        role: "user".to_string(),
+        content: prompt,
+    });
+    conversation.messages.push(ChatMessage {
+        role: "assistant".to_string(),
+        content: synthesis,
+    });
+    let excess = conversation.messages.len().saturating_sub(window * 2);
+    conversation.messages.drain(..excess);
+    Ok(())
+}
+
+fn prune_conversations(conversations: &mut BTreeMap<String, Conversation>) {
+    while conversations.len() >= MAX_RETAINED_CONVERSATIONS {
+        let oldest = conversations
+            .iter()
+            .min_by_key(|(_, x)| x.updated)
+            .map(|(id, _)| id.clone());
+        match oldest {
+            Some(id) => {
+                conversations.remove(&id);
+            }
+            None => break,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_conversation_history_window() {
+        let id = "test-conversation-window";
+        for turn in 0..4 {
+            append_turn(id, format!("q{}", turn), format!("a{}", turn), 2).unwrap();
+        }
+        let history = conversation_history(id, 2).unwrap();
+        let contents: Vec<&str> = history.iter().map(|x| x.content.as_str()).collect();
+        assert_eq!(contents, vec!["q2", "a2", "q3", "a3"]);
+        assert_eq!(history[0].role, "user");
+        assert_eq!(history[1].role, "assistant");
+        assert_eq!(conversation_history(id, 1).unwrap().len(), 2);
+        assert!(conversation_history("unknown", 2).unwrap().is_empty());
+    }
+}
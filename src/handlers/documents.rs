@@ -0,0 +1,334 @@
+use crate::api::schema::{Document, DocumentWriteRequest};
+use crate::config::load::{DocumentStoreSettings, Parameters};
+use crate::handlers::api_calls::{process_document_write, process_get_call};
+use crate::handlers::helper::{get_document_settings, get_document_store, get_http_settings};
+use custom_logger as log;
+use futures::future::BoxFuture;
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+
+// document persistence
+//
+// - remote     -> the document-service protocol (POST /write, GET /read?document=)
+// - filesystem -> one file per document in a local directory
+// - memory     -> kept in process, lost on restart (tests and standalone use)
+//
+// the backend is selected with document_store in the config, document names are
+// built from documents.name_template ({member}, {stage} and {title}) and the links
+// returned in the summary either use documents.public_url ({document}) or the
+// location reported by the store
+
+pub trait DocumentStore: Send + Sync {
+    fn read<'a>(
+        &'a self,
+        document: &'a str,
+    ) -> BoxFuture<'a, Result<String, Box<dyn std::error::Error>>>;
+    fn write<'a>(
+        &'a self,
+        document: &'a str,
+        content: &'a str,
+    ) -> BoxFuture<'a, Result<(), Box<dyn std::error::Error>>>;
+    fn health(&self) -> BoxFuture<'_, Result<String, Box<dyn std::error::Error>>>;
+    // where the document can be read from
+    fn location(&self, document: &str) -> String;
+}
+
+pub fn format_document_name(template: &str, member: &str, stage: &str, title: &str) -> String {
+    // the title is user supplied, so it is substituted last
+    template
+        .replace("{member}", member)
+        .replace("{stage}", stage)
+        .replace("{title}", title)
+}
+
+// percent-encodes a document name for use as a url query value, the name includes
+// the (user supplied) title so it may contain spaces, '&', '#', '?' or '/'
+pub fn encode_document(document: &str) -> String {
+    let mut encoded = String::with_capacity(document.len());
+    for byte in document.bytes() {
+        match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => {
+                encoded.push(byte as char)
+            }
+            _ => encoded.push_str(&format!("%{:02X}", byte)),
+        }
+    }
+    encoded
+}
+
+// the public link of a document, public_url contains a {document} placeholder
+pub fn public_document_url(public_url: &str, document: &str) -> String {
+    public_url.replace("{document}", &encode_document(document))
+}
+
+pub fn document_name(
+    member: &str,
+    stage: &str,
+    title: &str,
+) -> Result<String, Box<dyn std::error::Error>> {
+    let settings = get_document_settings()?;
+    Ok(format_document_name(
+        &settings.name_template,
+        member,
+        stage,
+        title,
+    ))
+}
+
+// the link to a stage document as returned in the summary
+pub fn document_link(
+    member: &str,
+    stage: &str,
+    title: &str,
+) -> Result<Document, Box<dyn std::error::Error>> {
+    let settings = get_document_settings()?;
+    let document = format_document_name(&settings.name_template, member, stage, title);
+    let url = match settings.public_url {
+        Some(public_url) => public_document_url(&public_url, &document),
+        None => get_document_store()?.location(&document),
+    };
+    Ok(Document {
+        name: member.to_string(),
+        url,
+    })
+}
+
+pub fn build_document_store(params: &Parameters) -> Arc<dyn DocumentStore> {
+    match &params.document_store {
+        DocumentStoreSettings::Remote => Arc::new(RemoteDocumentStore {
+            base_url: params.document_service_url.clone(),
+        }),
+        DocumentStoreSettings::Filesystem { path } => Arc::new(FilesystemDocumentStore {
+            path: PathBuf::from(path),
+        }),
+        DocumentStoreSettings::Memory => Arc::new(MemoryDocumentStore::default()),
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct RemoteDocumentStore {
+    pub base_url: String,
+}
+
+impl DocumentStore for RemoteDocumentStore {
+    fn read<'a>(
+        &'a self,
+        document: &'a str,
+    ) -> BoxFuture<'a, Result<String, Box<dyn std::error::Error>>> {
+        Box::pin(async move {
+            let timeout = get_http_settings()?.timeouts.for_stage("document");
+            process_get_call(self.location(document), timeout).await
+        })
+    }
+
+    fn write<'a>(
+        &'a self,
+        document: &'a str,
+        content: &'a str,
+    ) -> BoxFuture<'a, Result<(), Box<dyn std::error::Error>>> {
+        Box::pin(async move {
+            let data = DocumentWriteRequest {
+                document: document.to_string(),
+                content: content.to_string(),
+            };
+            process_document_write(format!("{}/write", self.base_url), data).await
+        })
+    }
+
+    fn health(&self) -> BoxFuture<'_, Result<String, Box<dyn std::error::Error>>> {
+        Box::pin(async move {
+            let timeout = get_http_settings()?.timeouts.for_stage("health");
+            process_get_call(format!("{}/v1/health", self.base_url), timeout).await
+        })
+    }
+
+    fn location(&self, document: &str) -> String {
+        format!(
+            "{}/read?document={}",
+            self.base_url,
+            encode_document(document)
+        )
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct FilesystemDocumentStore {
+    pub path: PathBuf,
+}
+
+impl FilesystemDocumentStore {
+    // the document name includes the (user supplied) title, so never allow it
+    // to leave the store directory
+    fn document_path(&self, document: &str) -> Result<PathBuf, Box<dyn std::error::Error>> {
+        if document.is_empty()
+            || document.starts_with('.')
+            || document.contains('/')
+            || document.contains('\\')
+        {
+            return Err(Box::from(format!(
+                "[document_path] invalid document name {}",
+                document
+            )));
+        }
+        Ok(self.path.join(document))
+    }
+}
+
+impl DocumentStore for FilesystemDocumentStore {
+    fn read<'a>(
+        &'a self,
+        document: &'a str,
+    ) -> BoxFuture<'a, Result<String, Box<dyn std::error::Error>>> {
+        Box::pin(async move {
+            let path = self.document_path(document)?;
+            let content = tokio::fs::read_to_string(&path)
+                .await
+                .map_err(|e| format!("[read] {} {}", path.display(), e))?;
+            Ok(content)
+        })
+    }
+
+    fn write<'a>(
+        &'a self,
+        document: &'a str,
+        content: &'a str,
+    ) -> BoxFuture<'a, Result<(), Box<dyn std::error::Error>>> {
+        Box::pin(async move {
+            let path = self.document_path(document)?;
+            tokio::fs::create_dir_all(&self.path).await?;
+            tokio::fs::write(&path, content)
+                .await
+                .map_err(|e| format!("[write] {} {}", path.display(), e))?;
+            log::debug!("[write] {}", path.display());
+            Ok(())
+        })
+    }
+
+    fn health(&self) -> BoxFuture<'_, Result<String, Box<dyn std::error::Error>>> {
+        Box::pin(async move {
+            tokio::fs::create_dir_all(&self.path).await?;
+            Ok(format!("filesystem {}", self.path.display()))
+        })
+    }
+
+    fn location(&self, document: &str) -> String {
+        let path = self.path.join(document);
+        let path = std::path::absolute(&path).unwrap_or(path);
+        // the url encodes the (user supplied) title, e.g. spaces, '#' or '?'
+        match reqwest::Url::from_file_path(&path) {
+            Ok(url) => url.to_string(),
+            Err(_) => format!("file://{}", path.display()),
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct MemoryDocumentStore {
+    documents: Mutex<BTreeMap<String, String>>,
+}
+
+impl DocumentStore for MemoryDocumentStore {
+    fn read<'a>(
+        &'a self,
+        document: &'a str,
+    ) -> BoxFuture<'a, Result<String, Box<dyn std::error::Error>>> {
+        Box::pin(async move {
+            let documents = self.documents.lock().map_err(|_| "mutex lock failed")?;
+            match documents.get(document) {
+                Some(content) => Ok(content.clone()),
+                None => Err(Box::from(format!("[read] document {} not found", document))),
+            }
+        })
+    }
+
+    fn write<'a>(
+        &'a self,
+        document: &'a str,
+        content: &'a str,
+    ) -> BoxFuture<'a, Result<(), Box<dyn std::error::Error>>> {
+        Box::pin(async move {
+            let mut documents = self.documents.lock().map_err(|_| "mutex lock failed")?;
+            documents.insert(document.to_string(), content.to_string());
+            Ok(())
+        })
+    }
+
+    fn health(&self) -> BoxFuture<'_, Result<String, Box<dyn std::error::Error>>> {
+        Box::pin(async move { Ok("memory".to_string()) })
+    }
+
+    fn location(&self, document: &str) -> String {
+        format!("memory://{}", document)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_format_document_name() {
+        let name = format_document_name(
+            "{member}-{stage}-{title}.md",
+            "gemini",
+            "chairman-summary",
+            "unikernel-adoption",
+        );
+        assert_eq!(name, "gemini-chairman-summary-unikernel-adoption.md");
+        let name = format_document_name("{title}_{stage}_{member}.md", "grok", "initial", "a");
+        assert_eq!(name, "a_initial_grok.md");
+    }
+
+    #[test]
+    fn test_document_urls_are_encoded() {
+        let document = format_document_name(
+            "{member}-{stage}-{title}.md",
+            "grok",
+            "initial",
+            "why & how? #1/2",
+        );
+        let store = RemoteDocumentStore {
+            base_url: "http://192.168.1.29:8080".to_string(),
+        };
+        assert_eq!(
+            store.location(&document),
+            "http://192.168.1.29:8080/read?document=grok-initial-why%20%26%20how%3F%20%231%2F2.md"
+        );
+        let url = public_document_url(
+            "https://unikernel-sandbox/document-store/read?document={document}",
+            &document,
+        );
+        let parsed = reqwest::Url::parse(&url).unwrap();
+        // a single query parameter holding the whole name, no fragment
+        let pairs: Vec<(String, String)> = parsed.query_pairs().into_owned().collect();
+        assert_eq!(pairs, vec![("document".to_string(), document.clone())]);
+        assert!(parsed.fragment().is_none());
+    }
+
+    #[tokio::test]
+    async fn test_memory_document_store() {
+        let store = MemoryDocumentStore::default();
+        store.write("gemini-initial-a.md", "hello").await.unwrap();
+        assert_eq!(store.read("gemini-initial-a.md").await.unwrap(), "hello");
+        assert!(store.read("gemini-ranking-a.md").await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_filesystem_document_store() {
+        let path = std::env::temp_dir().join(format!("council-docs-{}", std::process::id()));
+        let store = FilesystemDocumentStore { path: path.clone() };
+        store.write("grok-initial-a.md", "hello").await.unwrap();
+        assert_eq!(store.read("grok-initial-a.md").await.unwrap(), "hello");
+        assert!(store.write("../escape.md", "x").await.is_err());
+        assert!(store.read("grok/initial.md").await.is_err());
+        let location = store.location("grok-initial-why & how? #1.md");
+        let url = reqwest::Url::parse(&location).unwrap();
+        assert!(url.fragment().is_none() && url.query().is_none());
+        assert_eq!(
+            url.to_file_path().unwrap(),
+            std::path::absolute(path.join("grok-initial-why & how? #1.md")).unwrap()
+        );
+        let _ = std::fs::remove_dir_all(path);
+    }
+}
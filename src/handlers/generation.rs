@@ -0,0 +1,115 @@
+use crate::api::schema::{ChatMessage, ChatRequest, LLMCouncilRequestSchema};
+use crate::config::load::ModelSchema;
+
+// generation parameters of a member (or chairman) call
+//
+// the member defaults (generation in the config) are merged with the request
+//
+// - max_tokens    -> per stage, for the initial and revision stages the member limit caps
+//                    the request max_tokens, ranking defaults to 16384 and the chairman
+//                    to no limit
+// - temperature, top_p and stop -> the request value when set, otherwise the member default
+// - system_prompt -> sent as the first (system) message of every call to the member
+
+pub const DEFAULT_RANKING_MAX_TOKENS: usize = 16384;
+
+pub fn stage_max_tokens(
+    member: &ModelSchema,
+    stage: &str,
+    req: &LLMCouncilRequestSchema,
+) -> Option<usize> {
+    let limit = member.generation.max_tokens.get(stage).copied();
+    match stage {
+        "initial" | "revision" => Some(limit.map_or(req.max_tokens, |x| x.min(req.max_tokens))),
+        "ranking" => Some(limit.unwrap_or(DEFAULT_RANKING_MAX_TOKENS)),
+        _ => limit,
+    }
+}
+
+// the request sent to the member for a stage, history is replayed before the prompt
+pub fn member_request(
+    member: &ModelSchema,
+    stage: &str,
+    req: &LLMCouncilRequestSchema,
+    history: &[ChatMessage],
+    content: String,
+) -> ChatRequest {
+    let generation = &member.generation;
+    let max_tokens = stage_max_tokens(member, stage, req);
+    let mut request = ChatRequest::with_history(&member.model, history, content, max_tokens);
+    if let Some(system_prompt) = &generation.system_prompt {
+        request.messages.insert(
+            0,
+            ChatMessage {
+                role: "system".to_string(),
+                content: system_prompt.clone(),
+            },
+        );
+    }
+    request.temperature = req.temperature.or(generation.temperature);
+    request.top_p = req.top_p.or(generation.top_p);
+    request.stop = req.stop.clone().unwrap_or(generation.stop.clone());
+    request
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::load::GenerationSettings;
+    use std::collections::BTreeMap;
+
+    fn member(generation: GenerationSettings) -> ModelSchema {
+        ModelSchema {
+            generation,
+            ..crate::test_support::member(1, "grok")
+        }
+    }
+
+    #[test]
+    fn test_member_request_defaults() {
+        let req = LLMCouncilRequestSchema {
+            max_tokens: 2048,
+            ..Default::default()
+        };
+        let ms = member(GenerationSettings::default());
+        let initial = member_request(&ms, "initial", &req, &[], "q".to_string());
+        assert_eq!(initial.max_tokens, Some(2048));
+        assert_eq!(initial.messages.len(), 1);
+        assert_eq!(stage_max_tokens(&ms, "ranking", &req), Some(16384));
+        assert_eq!(stage_max_tokens(&ms, "chairman", &req), None);
+        // the request is unchanged when no parameters are set
+        let json = serde_json::to_value(&initial).unwrap();
+        assert!(json.get("temperature").is_none() && json.get("stop").is_none());
+    }
+
+    #[test]
+    fn test_member_request_merged() {
+        let ms = member(GenerationSettings {
+            max_tokens: BTreeMap::from([
+                ("initial".to_string(), 1024),
+                ("chairman".to_string(), 8192),
+            ]),
+            temperature: Some(0.2),
+            top_p: Some(0.9),
+            system_prompt: Some("be concise".to_string()),
+            stop: vec!["END".to_string()],
+        });
+        let req = LLMCouncilRequestSchema {
+            max_tokens: 2048,
+            temperature: Some(0.7),
+            ..Default::default()
+        };
+        let history = vec![ChatMessage {
+            role: "user".to_string(),
+            content: "earlier".to_string(),
+        }];
+        let initial = member_request(&ms, "initial", &req, &history, "q".to_string());
+        assert_eq!(initial.max_tokens, Some(1024));
+        assert_eq!(initial.temperature, Some(0.7));
+        assert_eq!(initial.top_p, Some(0.9));
+        assert_eq!(initial.stop, vec!["END"]);
+        assert_eq!(initial.messages[0].role, "system");
+        assert_eq!(initial.messages[1].content, "earlier");
+        assert_eq!(stage_max_tokens(&ms, "chairman", &req), Some(8192));
+    }
+}
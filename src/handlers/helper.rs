@@ -1,23 +1,18 @@
-use crate::MAP_LOOKUP;
-use crate::SEMAPHORE;
-use crate::config::load::ModelSchema;
+use crate::api::schema::{AggregationStrategy, ChairmanStrategy, PipelineStage, SelfVoteMode};
+use crate::config::load::{
+    BreakerPolicy, DocumentSettings, HttpSettings, ModelSchema, QuorumPolicy, RevisionSettings,
+};
+use crate::handlers::documents::DocumentStore;
+use crate::handlers::reload::{run_document_store, run_http_client, run_parameters};
+use std::sync::Arc;
+use std::time::Duration;
 
 // helper functions
-
-pub fn check_semaphore() -> Result<bool, Box<dyn std::error::Error>> {
-    let state = SEMAPHORE.lock()?;
-    Ok(*state)
-}
-
-pub fn set_semaphore(value: bool) -> Result<(), Box<dyn std::error::Error>> {
-    let mut state = SEMAPHORE.lock()?;
-    *state = value;
-    Ok(())
-}
+//
+// the getters read the configuration snapshot of the current run (see reload)
 
 pub fn get_council_chairman() -> Result<ModelSchema, Box<dyn std::error::Error>> {
-    let hm_guard = MAP_LOOKUP.lock().map_err(|_| "mutex lock failed")?;
-    let res_guard = hm_guard.as_ref();
+    let res_guard = run_parameters()?;
     let result = match res_guard {
         Some(value) => value.council_chairman.clone(),
         None => {
@@ -30,8 +25,7 @@ pub fn get_council_chairman() -> Result<ModelSchema, Box<dyn std::error::Error>>
 }
 
 pub fn get_council_members() -> Result<Vec<ModelSchema>, Box<dyn std::error::Error>> {
-    let hm_guard = MAP_LOOKUP.lock().map_err(|_| "mutex lock failed")?;
-    let res_guard = hm_guard.as_ref();
+    let res_guard = run_parameters()?;
     let result = match res_guard {
         Some(value) => value.council_members.clone(),
         None => {
@@ -43,16 +37,162 @@ pub fn get_council_members() -> Result<Vec<ModelSchema>, Box<dyn std::error::Err
     Ok(result)
 }
 
-pub fn get_document_store_url() -> Result<String, Box<dyn std::error::Error>> {
-    let hm_guard = MAP_LOOKUP.lock().map_err(|_| "mutex lock failed")?;
-    let res_guard = hm_guard.as_ref();
+pub fn get_document_store() -> Result<Arc<dyn DocumentStore>, Box<dyn std::error::Error>> {
+    match run_document_store()? {
+        Some(store) => Ok(store),
+        None => Err(Box::from(
+            "[get_document_store] document store not initialised",
+        )),
+    }
+}
+
+pub fn get_aggregation_strategy() -> Result<AggregationStrategy, Box<dyn std::error::Error>> {
+    let res_guard = run_parameters()?;
+    let result = match res_guard {
+        Some(value) => value.aggregation_strategy,
+        None => {
+            return Err(Box::from(
+                "[get_aggregation_strategy] retrieving aggregation_strategy parameter",
+            ));
+        }
+    };
+    Ok(result)
+}
+
+pub fn get_self_vote_mode() -> Result<SelfVoteMode, Box<dyn std::error::Error>> {
+    let res_guard = run_parameters()?;
     let result = match res_guard {
-        Some(value) => value.document_service_url.clone(),
+        Some(value) => value.self_vote,
         None => {
             return Err(Box::from(
-                "[get_document_store_url] retrieving document_service_url parameter",
+                "[get_self_vote_mode] retrieving self_vote parameter",
             ));
         }
     };
     Ok(result)
 }
+
+pub fn get_chairman_strategy() -> Result<ChairmanStrategy, Box<dyn std::error::Error>> {
+    let res_guard = run_parameters()?;
+    let result = match res_guard {
+        Some(value) => value.chairman_strategy,
+        None => {
+            return Err(Box::from(
+                "[get_chairman_strategy] retrieving chairman_strategy parameter",
+            ));
+        }
+    };
+    Ok(result)
+}
+
+pub fn get_quorum_policy() -> Result<QuorumPolicy, Box<dyn std::error::Error>> {
+    let res_guard = run_parameters()?;
+    let result = match res_guard {
+        Some(value) => value.quorum.clone(),
+        None => {
+            return Err(Box::from("[get_quorum_policy] retrieving quorum parameter"));
+        }
+    };
+    Ok(result)
+}
+
+pub fn get_http_settings() -> Result<HttpSettings, Box<dyn std::error::Error>> {
+    let res_guard = run_parameters()?;
+    let result = match res_guard {
+        Some(value) => value.http.clone(),
+        None => {
+            return Err(Box::from("[get_http_settings] retrieving http parameter"));
+        }
+    };
+    Ok(result)
+}
+
+// the member timeout when set, otherwise the timeout of the stage
+pub fn get_call_timeout(
+    member: &ModelSchema,
+    stage: &str,
+) -> Result<Duration, Box<dyn std::error::Error>> {
+    match member.timeout_secs {
+        Some(secs) => Ok(Duration::from_secs(secs)),
+        None => Ok(get_http_settings()?.timeouts.for_stage(stage)),
+    }
+}
+
+pub fn get_http_client() -> Result<reqwest::Client, Box<dyn std::error::Error>> {
+    // the client is reference counted, clones share the connection pool
+    match run_http_client()? {
+        Some(client) => Ok(client),
+        None => Err(Box::from("[get_http_client] http client not initialised")),
+    }
+}
+
+pub fn get_breaker_policy() -> Result<BreakerPolicy, Box<dyn std::error::Error>> {
+    let res_guard = run_parameters()?;
+    let result = match res_guard {
+        Some(value) => value.breaker.clone(),
+        None => {
+            return Err(Box::from(
+                "[get_breaker_policy] retrieving breaker parameter",
+            ));
+        }
+    };
+    Ok(result)
+}
+
+pub fn get_document_settings() -> Result<DocumentSettings, Box<dyn std::error::Error>> {
+    let res_guard = run_parameters()?;
+    let result = match res_guard {
+        Some(value) => value.documents.clone(),
+        None => {
+            return Err(Box::from(
+                "[get_document_settings] retrieving documents parameter",
+            ));
+        }
+    };
+    Ok(result)
+}
+
+pub fn get_history_window() -> Result<usize, Box<dyn std::error::Error>> {
+    let res_guard = run_parameters()?;
+    let result = match res_guard {
+        Some(value) => value.history_window,
+        None => {
+            return Err(Box::from(
+                "[get_history_window] retrieving history_window parameter",
+            ));
+        }
+    };
+    Ok(result)
+}
+
+pub fn get_revision_settings() -> Result<RevisionSettings, Box<dyn std::error::Error>> {
+    let res_guard = run_parameters()?;
+    let result = match res_guard {
+        Some(value) => value.revision.clone(),
+        None => {
+            return Err(Box::from(
+                "[get_revision_settings] retrieving revision parameter",
+            ));
+        }
+    };
+    Ok(result)
+}
+
+pub fn get_pipeline(name: &str) -> Result<Vec<PipelineStage>, Box<dyn std::error::Error>> {
+    let res_guard = run_parameters()?;
+    let result = match res_guard {
+        Some(value) => match value.pipelines.get(name) {
+            Some(stages) => stages.clone(),
+            None => {
+                return Err(Box::from(format!(
+                    "[get_pipeline] invalid request unknown pipeline {}",
+                    name
+                )));
+            }
+        },
+        None => {
+            return Err(Box::from("[get_pipeline] retrieving pipelines parameter"));
+        }
+    };
+    Ok(result)
+}
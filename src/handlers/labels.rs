@@ -0,0 +1,123 @@
+use std::collections::BTreeMap;
+
+// per judge label assignment
+//
+// every judge receives its own shuffled ordering of the responses, labelled
+// "Response A", "Response B", ... in the order shown, so that no model is always
+// shown first (position bias), the shuffle is seeded so a run can be reproduced
+// and cached rankings can be translated back to models
+
+// splitmix64, small and good enough for shuffling (not cryptographic)
+#[derive(Debug, Clone)]
+pub struct SplitMix64 {
+    state: u64,
+}
+
+impl SplitMix64 {
+    pub fn new(seed: u64) -> Self {
+        SplitMix64 { state: seed }
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        self.state = self.state.wrapping_add(0x9e3779b97f4a7c15);
+        let mut z = self.state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+        z ^ (z >> 31)
+    }
+
+    // uniform in 0..bound (bound > 0)
+    pub fn next_below(&mut self, bound: usize) -> usize {
+        (self.next_u64() % bound as u64) as usize
+    }
+}
+
+// fnv-1a, used to derive stable seeds from strings
+pub fn hash_str(value: &str) -> u64 {
+    let mut hash: u64 = 0xcbf29ce484222325;
+    for byte in value.bytes() {
+        hash ^= byte as u64;
+        hash = hash.wrapping_mul(0x100000001b3);
+    }
+    hash
+}
+
+// the seed used when the request does not set one, derived from the title so
+// that a stage reused from the document store maps the labels the same way
+pub fn default_seed(title: &str) -> u64 {
+    hash_str(title)
+}
+
+pub fn label_for(index: usize) -> String {
+    format!("Response {}", (65 + index as u8) as char)
+}
+
+// returns judge -> (label -> member) for the responses each judge is shown,
+// when omit_own is set the judge's own response is left out
+pub fn assign_judge_labels(
+    members: &[String],
+    judges: &[String],
+    seed: u64,
+    omit_own: bool,
+) -> BTreeMap<String, BTreeMap<String, String>> {
+    let mut assignments: BTreeMap<String, BTreeMap<String, String>> = BTreeMap::new();
+    for judge in judges.iter() {
+        let mut order: Vec<String> = members
+            .iter()
+            .filter(|x| !(omit_own && *x == judge))
+            .cloned()
+            .collect();
+        order.sort();
+        let mut rng = SplitMix64::new(seed ^ hash_str(judge));
+        // fisher-yates
+        for i in (1..order.len()).rev() {
+            let j = rng.next_below(i + 1);
+            order.swap(i, j);
+        }
+        let mapping = order
+            .into_iter()
+            .enumerate()
+            .map(|(i, member)| (label_for(i), member))
+            .collect();
+        assignments.insert(judge.clone(), mapping);
+    }
+    assignments
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn names(values: &[&str]) -> Vec<String> {
+        values.iter().map(|x| x.to_string()).collect()
+    }
+
+    #[test]
+    fn test_assign_judge_labels_is_seeded() {
+        let members = names(&["anthropic", "gemini", "grok", "openai"]);
+        let first = assign_judge_labels(&members, &members, 42, false);
+        let second = assign_judge_labels(&members, &members, 42, false);
+        assert_eq!(first, second);
+        for mapping in first.values() {
+            let mut assigned: Vec<String> = mapping.values().cloned().collect();
+            assigned.sort();
+            assert_eq!(assigned, members);
+        }
+        // with 4 judges it is very unlikely that all orderings are identical
+        let orderings: Vec<Vec<String>> = first
+            .values()
+            .map(|x| x.values().cloned().collect())
+            .collect();
+        assert!(orderings.iter().any(|x| x != &orderings[0]));
+    }
+
+    #[test]
+    fn test_assign_judge_labels_omit_own() {
+        let members = names(&["anthropic", "gemini", "grok"]);
+        let assignments = assign_judge_labels(&members, &members, 7, true);
+        let gemini = &assignments["gemini"];
+        assert_eq!(gemini.len(), 2);
+        assert!(!gemini.values().any(|x| x == "gemini"));
+        assert!(gemini.contains_key("Response A") && gemini.contains_key("Response B"));
+    }
+}
@@ -1,4 +1,19 @@
+pub mod admin;
+pub mod aggregation;
 pub mod api_calls;
+pub mod breaker;
+pub mod chairman;
 pub mod controller;
+pub mod conversations;
+pub mod documents;
+pub mod generation;
 pub mod helper;
+pub mod labels;
+pub mod openai;
+pub mod pipeline;
+pub mod prompts;
+pub mod queue;
+pub mod ranking;
+pub mod reload;
+pub mod runs;
 pub mod service;
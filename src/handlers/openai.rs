@@ -0,0 +1,293 @@
+use crate::api::schema::{
+    ChatChoice, ChatCompletionRequest, ChatCompletionResponse, ChatMessage, ChatUsage,
+    LLMCouncilRequestSchema, MemberCompletion, MemberReply, ProgressEvent, ResponseSummary,
+};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+// conversions between the openai chat completion format and the council request/summary,
+// this allows openai sdk clients to point at the council using model "council"
+
+pub const COUNCIL_MODEL: &str = "council";
+
+// an openai request always names the model, a council request can also carry
+// messages (earlier turns before the prompt) but has no model
+pub fn is_chat_completion_request(value: &serde_json::Value) -> bool {
+    value.get("model").is_some()
+}
+
+pub fn to_council_request(
+    req: ChatCompletionRequest,
+    default_title: &str,
+) -> Result<LLMCouncilRequestSchema, Box<dyn std::error::Error>> {
+    if req.model != COUNCIL_MODEL {
+        return Err(Box::from(format!(
+            "[to_council_request] invalid request model {} (expected {})",
+            req.model, COUNCIL_MODEL
+        )));
+    }
+    // the council answers the latest user message, the system message and the earlier
+    // turns are sent to the members before it
+    let Some(last) = req.messages.iter().rposition(|x| x.role == "user") else {
+        return Err(Box::from(
+            "[to_council_request] invalid request no user message found",
+        ));
+    };
+    if last + 1 != req.messages.len() {
+        return Err(Box::from(
+            "[to_council_request] invalid request the last message must be a user message",
+        ));
+    }
+    if let Some(message) = req
+        .messages
+        .iter()
+        .find(|x| !["system", "user", "assistant"].contains(&x.role.as_str()))
+    {
+        return Err(Box::from(format!(
+            "[to_council_request] invalid request unsupported message role {}",
+            message.role
+        )));
+    }
+    let mut messages = req.messages;
+    let prompt = messages.remove(last).content;
+    Ok(LLMCouncilRequestSchema {
+        title: req.title.unwrap_or(default_title.to_string()),
+        prompt,
+        max_tokens: req.max_tokens.unwrap_or(2048),
+        flow_control: req.flow_control,
+        pipeline: req.pipeline,
+        stages: req.stages,
+        run_async: false,
+        stream: req.stream,
+        aggregation: req.aggregation,
+        self_vote: req.self_vote,
+        chairman_strategy: req.chairman_strategy,
+        seed: req.seed,
+        conversation_id: req.conversation_id,
+        messages,
+        temperature: req.temperature,
+        top_p: req.top_p,
+        stop: req.stop,
+    })
+}
+
+pub fn to_chat_completion(
+    run_id: &str,
+    content: String,
+    summary: ResponseSummary,
+) -> ChatCompletionResponse {
+    // the usage of a council run is the sum of all member and chairman calls
+    let usage = summary
+        .usage
+        .values()
+        .fold(ChatUsage::default(), |acc, x| ChatUsage {
+            prompt_tokens: acc.prompt_tokens + x.prompt_tokens,
+            completion_tokens: acc.completion_tokens + x.completion_tokens,
+            total_tokens: acc.total_tokens + x.total_tokens,
+        });
+    ChatCompletionResponse {
+        id: format!("chatcmpl-{}", run_id),
+        object: "chat.completion".to_string(),
+        created: unix_seconds(),
+        model: COUNCIL_MODEL.to_string(),
+        choices: vec![ChatChoice {
+            index: 0,
+            message: Some(ChatMessage {
+                role: "assistant".to_string(),
+                content,
+            }),
+            delta: None,
+            finish_reason: Some("stop".to_string()),
+        }],
+        usage: Some(usage),
+        council: Some(summary),
+    }
+}
+
+// parses the chat completion body returned by a council member
+pub fn to_member_reply(contents: &str) -> Result<MemberReply, Box<dyn std::error::Error>> {
+    let completion: MemberCompletion = serde_json::from_str(contents)
+        .map_err(|e| format!("[to_member_reply] invalid chat completion {}", e))?;
+    let choice = completion
+        .choices
+        .into_iter()
+        .find(|x| x.message.is_some())
+        .ok_or("[to_member_reply] chat completion has no message")?;
+    Ok(MemberReply {
+        text: choice.message.and_then(|x| x.content).unwrap_or_default(),
+        finish_reason: choice.finish_reason,
+        usage: completion.usage,
+    })
+}
+
+// documents stored by earlier versions hold the raw chat completion body
+// instead of the extracted text, so fall back to the contents as is
+pub fn extract_message_content(contents: &str) -> String {
+    match to_member_reply(contents) {
+        Ok(reply) => reply.text,
+        Err(_) => contents.to_string(),
+    }
+}
+
+// maps the progress events of a run to an openai compatible event stream,
+// intermediate events are sent as sse comments (ignored by clients, but they keep
+// the connection alive), the synthesis is sent as a single chunk followed by [DONE]
+pub fn to_chat_stream_frame(chairman_content: &mut String, event: ProgressEvent) -> String {
+    match event.event.as_str() {
+        "chairman" => {
+            if let Some(serde_json::Value::String(contents)) = &event.data {
+                *chairman_content = extract_message_content(contents);
+            }
+            ": chairman completed\n\n".to_string()
+        }
+        "summary" => {
+            let summary: Option<ResponseSummary> = event
+                .data
+                .and_then(|value| serde_json::from_value(value).ok());
+            let chunk = ChatCompletionResponse {
+                id: format!("chatcmpl-{}", event.run_id),
+                object: "chat.completion.chunk".to_string(),
+                created: unix_seconds(),
+                model: COUNCIL_MODEL.to_string(),
+                choices: vec![ChatChoice {
+                    index: 0,
+                    message: None,
+                    delta: Some(ChatMessage {
+                        role: "assistant".to_string(),
+                        content: chairman_content.clone(),
+                    }),
+                    finish_reason: Some("stop".to_string()),
+                }],
+                usage: None,
+                council: summary,
+            };
+            let json = serde_json::to_string(&chunk).unwrap_or_default();
+            format!("data: {}\n\ndata: [DONE]\n\n", json)
+        }
+        "error" => {
+            let message = event.data.unwrap_or_default();
+            format!(
+                "data: {}\n\n",
+                serde_json::json!({ "error": { "message": message, "type": "council_error" } })
+            )
+        }
+        _ => format!(
+            ": {} {} {}\n\n",
+            event.event,
+            event.member.unwrap_or_default(),
+            event.status.unwrap_or_default()
+        ),
+    }
+}
+
+fn unix_seconds() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|x| x.as_secs())
+        .unwrap_or(0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::api::schema::{AggregationStrategy, ChairmanStrategy, ChatRequest};
+
+    #[test]
+    fn test_is_chat_completion_request() {
+        let chat = serde_json::json!({ "model": "council", "messages": [] });
+        assert!(is_chat_completion_request(&chat));
+        let council = serde_json::json!({
+            "title": "t",
+            "prompt": "p",
+            "max_tokens": 16,
+            "messages": [{"role": "system", "content": "be brief"}]
+        });
+        assert!(!is_chat_completion_request(&council));
+    }
+
+    #[test]
+    fn test_to_council_request() {
+        let req: ChatCompletionRequest = serde_json::from_str(
+            r#"{ "model": "council", "messages": [
+                {"role": "system", "content": "be brief"},
+                {"role": "user", "content": "what is a \"unikernel\"?\nexplain"}
+            ] }"#,
+        )
+        .unwrap();
+        let council = to_council_request(req.clone(), "run-1").unwrap();
+        assert_eq!(council.prompt, "what is a \"unikernel\"?\nexplain");
+        assert_eq!(council.title, "run-1");
+        assert_eq!(council.flow_control, None);
+        assert!(council.stages.is_empty());
+        // the system message is sent to the members before the prompt
+        assert_eq!(
+            council.messages,
+            vec![ChatMessage {
+                role: "system".to_string(),
+                content: "be brief".to_string()
+            }]
+        );
+
+        let mut other = req.clone();
+        other.model = "gpt-5.2".to_string();
+        assert!(to_council_request(other, "run-1").is_err());
+
+        let req: ChatCompletionRequest = serde_json::from_str(
+            r#"{ "model": "council", "seed": 7, "aggregation": "copeland",
+                "chairman_strategy": "top-ranked", "messages": [
+                {"role": "user", "content": "first"},
+                {"role": "assistant", "content": "answer"},
+                {"role": "user", "content": "follow-up"}
+            ] }"#,
+        )
+        .unwrap();
+        let council = to_council_request(req.clone(), "run-2").unwrap();
+        assert_eq!(council.prompt, "follow-up");
+        assert_eq!(council.messages.len(), 2);
+        assert_eq!(council.seed, Some(7));
+        assert_eq!(council.aggregation, Some(AggregationStrategy::Copeland));
+        assert_eq!(council.chairman_strategy, Some(ChairmanStrategy::TopRanked));
+
+        let mut trailing = req;
+        trailing.messages.push(ChatMessage {
+            role: "assistant".to_string(),
+            content: "prefill".to_string(),
+        });
+        let err = to_council_request(trailing, "run-3").unwrap_err();
+        assert!(err.to_string().contains("invalid request"));
+    }
+
+    #[test]
+    fn test_extract_message_content() {
+        let body = r#"{ "id": "x", "object": "chat.completion", "created": 1, "model": "m",
+            "choices": [{ "index": 0, "message": {"role": "assistant", "content": "hello"}, "finish_reason": "stop" }] }"#;
+        assert_eq!(extract_message_content(body), "hello");
+        assert_eq!(extract_message_content("plain text"), "plain text");
+    }
+
+    #[test]
+    fn test_to_member_reply() {
+        let body = r#"{ "id": "x", "object": "chat.completion", "created": 1, "model": "m",
+            "choices": [{ "index": 0, "message": {"role": "assistant", "content": "a \"quoted\"\nanswer"}, "finish_reason": "length" }],
+            "usage": { "prompt_tokens": 10, "completion_tokens": 20, "total_tokens": 30 } }"#;
+        let reply = to_member_reply(body).unwrap();
+        assert_eq!(reply.text, "a \"quoted\"\nanswer");
+        assert_eq!(reply.finish_reason, Some("length".to_string()));
+        assert_eq!(reply.usage.unwrap().total_tokens, 30);
+        assert!(to_member_reply("{}").is_err());
+
+        // servers that omit the metadata or send a null content
+        let body = r#"{ "choices": [{ "message": {"role": "assistant", "content": null}, "finish_reason": "content_filter" }] }"#;
+        let reply = to_member_reply(body).unwrap();
+        assert_eq!(reply.text, "");
+        assert_eq!(reply.finish_reason, Some("content_filter".to_string()));
+        assert!(reply.usage.is_none());
+    }
+
+    #[test]
+    fn test_chat_request_escapes_prompt() {
+        let req = ChatRequest::with_history("m", &[], "say \"hi\"\n".to_string(), Some(16));
+        let json = serde_json::to_string(&req).unwrap();
+        let back: ChatRequest = serde_json::from_str(&json).unwrap();
+        assert_eq!(back, req);
+    }
+}
@@ -0,0 +1,189 @@
+use crate::api::schema::{LLMCouncilRequestSchema, PipelineStage, StageExecution, StageMode};
+use crate::handlers::helper::get_pipeline;
+
+// resolves the stages of a request
+//
+// the pipeline (config) sets which stages are executed and in which order, the
+// request sets the mode of each stage (run when not set), a stage that is run or
+// reused needs the stages it depends on to be earlier in the pipeline and not skipped
+
+pub const DEFAULT_PIPELINE: &str = "default";
+
+impl PipelineStage {
+    pub fn name(&self) -> &'static str {
+        match self {
+            PipelineStage::Answer => "answer",
+            PipelineStage::Revise => "revise",
+            PipelineStage::Rank => "rank",
+            PipelineStage::Synthesize => "synthesize",
+        }
+    }
+
+    // the stages whose documents this stage reads
+    pub fn requires(&self) -> &'static [PipelineStage] {
+        match self {
+            PipelineStage::Answer => &[],
+            PipelineStage::Revise => &[PipelineStage::Answer],
+            PipelineStage::Rank => &[PipelineStage::Answer],
+            PipelineStage::Synthesize => &[PipelineStage::Answer, PipelineStage::Rank],
+        }
+    }
+
+    // the stages that must not come earlier in the pipeline
+    fn precedes(&self) -> &'static [PipelineStage] {
+        match self {
+            PipelineStage::Revise => &[PipelineStage::Rank, PipelineStage::Synthesize],
+            _ => &[],
+        }
+    }
+
+    // bit used by the deprecated flow_control bitmask, the revisions are part of the answer step
+    fn flow_control_bit(&self) -> u8 {
+        match self {
+            PipelineStage::Answer | PipelineStage::Revise => 1,
+            PipelineStage::Rank => 2,
+            PipelineStage::Synthesize => 4,
+        }
+    }
+}
+
+pub fn resolve_pipeline(
+    req: &LLMCouncilRequestSchema,
+) -> Result<(String, Vec<StageExecution>), Box<dyn std::error::Error>> {
+    let name = req.pipeline.clone().unwrap_or(DEFAULT_PIPELINE.to_string());
+    let pipeline = get_pipeline(&name)?;
+    let stages = resolve_stages(&pipeline, req)?;
+    Ok((name, stages))
+}
+
+pub fn resolve_stages(
+    pipeline: &[PipelineStage],
+    req: &LLMCouncilRequestSchema,
+) -> Result<Vec<StageExecution>, Box<dyn std::error::Error>> {
+    validate_pipeline(pipeline)?;
+    if let Some(stage) = req.stages.keys().find(|x| !pipeline.contains(x)) {
+        return Err(Box::from(format!(
+            "[resolve_stages] invalid request stage {} is not part of the pipeline",
+            stage.name()
+        )));
+    }
+    let mut stages: Vec<StageExecution> = vec![];
+    for stage in pipeline.iter() {
+        let mode = match (req.stages.get(stage), req.flow_control) {
+            (Some(mode), _) => *mode,
+            (None, Some(bits)) if bits & stage.flow_control_bit() == 0 => StageMode::Reuse,
+            _ => StageMode::Run,
+        };
+        if mode != StageMode::Skip {
+            let skipped = stage.requires().iter().find(|x| {
+                stages
+                    .iter()
+                    .any(|s| s.stage == **x && s.mode == StageMode::Skip)
+            });
+            if let Some(required) = skipped {
+                return Err(Box::from(format!(
+                    "[resolve_stages] invalid request stage {} requires stage {} which is skipped",
+                    stage.name(),
+                    required.name()
+                )));
+            }
+        }
+        stages.push(StageExecution {
+            stage: *stage,
+            mode,
+        });
+    }
+    Ok(stages)
+}
+
+// every stage at most once and after the stages it depends on
+pub fn validate_pipeline(pipeline: &[PipelineStage]) -> Result<(), Box<dyn std::error::Error>> {
+    for (i, stage) in pipeline.iter().enumerate() {
+        if pipeline[..i].contains(stage) {
+            return Err(Box::from(format!(
+                "[validate_pipeline] stage {} is defined more than once",
+                stage.name()
+            )));
+        }
+        if let Some(required) = stage.requires().iter().find(|x| !pipeline[..i].contains(x)) {
+            return Err(Box::from(format!(
+                "[validate_pipeline] stage {} requires stage {} earlier in the pipeline",
+                stage.name(),
+                required.name()
+            )));
+        }
+        if let Some(later) = stage.precedes().iter().find(|x| pipeline[..i].contains(x)) {
+            return Err(Box::from(format!(
+                "[validate_pipeline] stage {} must come before stage {}",
+                stage.name(),
+                later.name()
+            )));
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pipeline() -> Vec<PipelineStage> {
+        vec![
+            PipelineStage::Answer,
+            PipelineStage::Rank,
+            PipelineStage::Synthesize,
+        ]
+    }
+
+    fn modes(stages: &[StageExecution]) -> Vec<StageMode> {
+        stages.iter().map(|x| x.mode).collect()
+    }
+
+    #[test]
+    fn test_resolve_stages() {
+        let mut req = LLMCouncilRequestSchema::default();
+        let stages = resolve_stages(&pipeline(), &req).unwrap();
+        assert_eq!(modes(&stages), vec![StageMode::Run; 3]);
+
+        // the deprecated bitmask reuses the stages that are not set
+        req.flow_control = Some(4);
+        let stages = resolve_stages(&pipeline(), &req).unwrap();
+        assert_eq!(
+            modes(&stages),
+            vec![StageMode::Reuse, StageMode::Reuse, StageMode::Run]
+        );
+
+        req.flow_control = None;
+        req.stages
+            .insert(PipelineStage::Synthesize, StageMode::Skip);
+        let stages = resolve_stages(&pipeline(), &req).unwrap();
+        assert_eq!(
+            modes(&stages),
+            vec![StageMode::Run, StageMode::Run, StageMode::Skip]
+        );
+    }
+
+    #[test]
+    fn test_resolve_stages_invalid() {
+        let mut req = LLMCouncilRequestSchema::default();
+        req.stages.insert(PipelineStage::Rank, StageMode::Skip);
+        let err = resolve_stages(&pipeline(), &req).unwrap_err();
+        assert!(err.to_string().contains("invalid request"));
+
+        let mut req = LLMCouncilRequestSchema::default();
+        req.stages.insert(PipelineStage::Synthesize, StageMode::Run);
+        let short = vec![PipelineStage::Answer, PipelineStage::Rank];
+        assert!(resolve_stages(&short, &req).is_err());
+
+        let unordered = vec![PipelineStage::Rank, PipelineStage::Answer];
+        assert!(validate_pipeline(&unordered).is_err());
+        let unordered = vec![PipelineStage::Revise, PipelineStage::Answer];
+        assert!(validate_pipeline(&unordered).is_err());
+        let late = vec![
+            PipelineStage::Answer,
+            PipelineStage::Rank,
+            PipelineStage::Revise,
+        ];
+        assert!(validate_pipeline(&late).is_err());
+    }
+}
@@ -0,0 +1,296 @@
+use crate::config::load::{ModelSchema, PromptSettings};
+use crate::handlers::reload::run_prompts;
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::Path;
+
+// prompt templates for the revision, ranking and chairman stages
+//
+// the templates are read from files (relative to the config directory) at startup,
+// placeholders are written as {name}:
+//
+// - {question}  -> the user prompt
+// - {responses} -> the (anonymized) initial responses
+// - {rankings}  -> the peer rankings (chairman only)
+// - {trust}     -> the trust in each response, empty unless configured (chairman only)
+// - {labels}    -> the labels the judge is asked to rank (ranking only)
+// - {answer}    -> the member's own latest answer (revision only)
+//
+// a council member can have its own templates (overrides), the version of the
+// template set is recorded with every run
+
+pub const RANKING: &str = "ranking";
+pub const REVISION: &str = "revision";
+pub const CHAIRMAN: &str = "chairman";
+
+// used when no template file is configured
+const DEFAULT_RANKING_TEMPLATE: &str = include_str!("../../config/prompts/ranking-v1.md");
+const DEFAULT_REVISION_TEMPLATE: &str = include_str!("../../config/prompts/revision-v1.md");
+const DEFAULT_CHAIRMAN_TEMPLATE: &str = include_str!("../../config/prompts/chairman-v2.md");
+
+#[derive(Debug, Clone, Default)]
+pub struct PromptTemplates {
+    pub version: String,
+    // kind -> template
+    templates: BTreeMap<String, String>,
+    // member -> (kind -> template)
+    overrides: BTreeMap<String, BTreeMap<String, String>>,
+}
+
+impl PromptTemplates {
+    pub fn template(&self, kind: &str, member: &str) -> Option<&String> {
+        self.overrides
+            .get(member)
+            .and_then(|x| x.get(kind))
+            .or_else(|| self.templates.get(kind))
+    }
+}
+
+fn required_placeholders(kind: &str) -> &'static [&'static str] {
+    match kind {
+        RANKING => &["question", "responses"],
+        REVISION => &["question", "answer", "responses"],
+        CHAIRMAN => &["question", "responses", "rankings"],
+        _ => &[],
+    }
+}
+
+// returns an error listing the required placeholders missing from the template
+pub fn validate_template(kind: &str, template: &str) -> Result<(), Box<dyn std::error::Error>> {
+    let missing: Vec<&str> = required_placeholders(kind)
+        .iter()
+        .filter(|x| !template.contains(&format!("{{{}}}", x)))
+        .cloned()
+        .collect();
+    if !missing.is_empty() {
+        return Err(Box::from(format!(
+            "[validate_template] {} template is missing placeholders {}",
+            kind,
+            missing.join(", ")
+        )));
+    }
+    Ok(())
+}
+
+// a trust value is only passed to the chairman through {trust}, when a council member
+// sets trust every chairman template (overrides included) must have the placeholder
+pub fn validate_trust_placeholder(
+    templates: &PromptTemplates,
+    council: &[ModelSchema],
+) -> Result<(), Box<dyn std::error::Error>> {
+    let Some(member) = council.iter().find(|x| x.trust.is_some()) else {
+        return Ok(());
+    };
+    let chairman_templates = templates
+        .templates
+        .get(CHAIRMAN)
+        .into_iter()
+        .chain(templates.overrides.values().filter_map(|x| x.get(CHAIRMAN)));
+    for template in chairman_templates {
+        if !template.contains("{trust}") {
+            return Err(Box::from(format!(
+                "[validate_trust_placeholder] {} sets trust, chairman template is missing placeholder trust",
+                member.name
+            )));
+        }
+    }
+    Ok(())
+}
+
+fn read_template(
+    config_dir: &Path,
+    kind: &str,
+    file: &Option<String>,
+    default: &str,
+) -> Result<String, Box<dyn std::error::Error>> {
+    let template = match file {
+        Some(file) => {
+            let path = config_dir.join(file);
+            fs::read_to_string(&path)
+                .map_err(|e| format!("[read_template] {} {} {}", kind, path.display(), e))?
+        }
+        None => default.to_string(),
+    };
+    validate_template(kind, &template)?;
+    Ok(template)
+}
+
+pub fn load_prompt_templates(
+    settings: &PromptSettings,
+    config_dir: &Path,
+) -> Result<PromptTemplates, Box<dyn std::error::Error>> {
+    let mut templates = BTreeMap::new();
+    templates.insert(
+        RANKING.to_string(),
+        read_template(
+            config_dir,
+            RANKING,
+            &settings.ranking,
+            DEFAULT_RANKING_TEMPLATE,
+        )?,
+    );
+    templates.insert(
+        REVISION.to_string(),
+        read_template(
+            config_dir,
+            REVISION,
+            &settings.revision,
+            DEFAULT_REVISION_TEMPLATE,
+        )?,
+    );
+    templates.insert(
+        CHAIRMAN.to_string(),
+        read_template(
+            config_dir,
+            CHAIRMAN,
+            &settings.chairman,
+            DEFAULT_CHAIRMAN_TEMPLATE,
+        )?,
+    );
+    let mut overrides: BTreeMap<String, BTreeMap<String, String>> = BTreeMap::new();
+    for (member, files) in settings.overrides.iter() {
+        let entry = overrides.entry(member.clone()).or_default();
+        for (kind, file) in [
+            (RANKING, &files.ranking),
+            (REVISION, &files.revision),
+            (CHAIRMAN, &files.chairman),
+        ] {
+            if file.is_some() {
+                entry.insert(kind.to_string(), read_template(config_dir, kind, file, "")?);
+            }
+        }
+    }
+    Ok(PromptTemplates {
+        version: settings.version.clone(),
+        templates,
+        overrides,
+    })
+}
+
+// substitutes the placeholders in a single pass, so braces in the values
+// (e.g. code in a response) are never treated as placeholders
+pub fn render(template: &str, values: &[(&str, &str)]) -> String {
+    let mut result = String::with_capacity(template.len());
+    let mut rest = template;
+    while let Some(start) = rest.find('{') {
+        result.push_str(&rest[..start]);
+        let candidate = &rest[start + 1..];
+        let value = candidate.find('}').and_then(|end| {
+            let name = &candidate[..end];
+            values
+                .iter()
+                .find(|(key, _)| *key == name)
+                .map(|(_, value)| (*value, end))
+        });
+        match value {
+            Some((value, end)) => {
+                result.push_str(value);
+                rest = &candidate[end + 1..];
+            }
+            None => {
+                result.push('{');
+                rest = candidate;
+            }
+        }
+    }
+    result.push_str(rest);
+    result
+}
+
+// renders the template of the given kind for a member, returns the prompt
+pub fn render_prompt(
+    kind: &str,
+    member: &str,
+    values: &[(&str, &str)],
+) -> Result<String, Box<dyn std::error::Error>> {
+    let templates = run_prompts()?.ok_or("[render_prompt] prompt templates not loaded")?;
+    let template = templates
+        .template(kind, member)
+        .ok_or(format!("[render_prompt] no {} template", kind))?;
+    Ok(render(template, values))
+}
+
+pub fn get_prompt_version() -> Result<String, Box<dyn std::error::Error>> {
+    match run_prompts()? {
+        Some(templates) => Ok(templates.version.clone()),
+        None => Err(Box::from(
+            "[get_prompt_version] prompt templates not loaded",
+        )),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_render_single_pass() {
+        let result = render(
+            "Question {question}\n{responses}\n{unknown} {",
+            &[
+                ("question", "what is {responses}?"),
+                ("responses", "fn main() {}"),
+            ],
+        );
+        assert_eq!(
+            result,
+            "Question what is {responses}?\nfn main() {}\n{unknown} {"
+        );
+    }
+
+    #[test]
+    fn test_default_templates_are_valid() {
+        assert!(validate_template(RANKING, DEFAULT_RANKING_TEMPLATE).is_ok());
+        assert!(validate_template(REVISION, DEFAULT_REVISION_TEMPLATE).is_ok());
+        assert!(validate_template(CHAIRMAN, DEFAULT_CHAIRMAN_TEMPLATE).is_ok());
+        let err = validate_template(CHAIRMAN, "{question} {responses}").unwrap_err();
+        assert!(err.to_string().contains("rankings"));
+    }
+
+    #[test]
+    fn test_trust_requires_placeholder() {
+        let mut member = crate::test_support::member(1, "gemini");
+        let mut templates =
+            load_prompt_templates(&PromptSettings::default(), Path::new("config")).unwrap();
+        templates.templates.insert(
+            CHAIRMAN.to_string(),
+            "{question}{responses}{rankings}".to_string(),
+        );
+        // no trust configured, the placeholder is optional
+        assert!(validate_trust_placeholder(&templates, std::slice::from_ref(&member)).is_ok());
+        member.trust = Some(0.5);
+        let err =
+            validate_trust_placeholder(&templates, std::slice::from_ref(&member)).unwrap_err();
+        assert!(err.to_string().contains("gemini sets trust"));
+        templates
+            .templates
+            .insert(CHAIRMAN.to_string(), DEFAULT_CHAIRMAN_TEMPLATE.to_string());
+        assert!(validate_trust_placeholder(&templates, std::slice::from_ref(&member)).is_ok());
+        templates.overrides.insert(
+            "grok".to_string(),
+            BTreeMap::from([(
+                CHAIRMAN.to_string(),
+                "{question}{responses}{rankings}".to_string(),
+            )]),
+        );
+        assert!(validate_trust_placeholder(&templates, &[member]).is_err());
+    }
+
+    #[test]
+    fn test_template_overrides() {
+        let settings = PromptSettings::default();
+        let mut templates = load_prompt_templates(&settings, Path::new("config")).unwrap();
+        templates.overrides.insert(
+            "grok".to_string(),
+            BTreeMap::from([(RANKING.to_string(), "{question}{responses}".to_string())]),
+        );
+        assert_eq!(
+            templates.template(RANKING, "grok").unwrap(),
+            "{question}{responses}"
+        );
+        assert_eq!(
+            templates.template(RANKING, "gemini").unwrap(),
+            DEFAULT_RANKING_TEMPLATE
+        );
+    }
+}
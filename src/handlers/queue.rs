@@ -0,0 +1,169 @@
+use crate::RUN_QUEUE;
+use std::collections::VecDeque;
+use std::sync::{Mutex, MutexGuard};
+use tokio::sync::Notify;
+
+// bounded FIFO run queue
+//
+// a run first takes a ticket (fails immediately if the queue is full) and then
+// waits for admission, runs are admitted strictly in the order the tickets were
+// issued as long as less than max_concurrent runs are executing
+//
+// both the ticket and the permit release their slot on drop, so a panic or a
+// dropped connection can never leave the queue stuck
+
+#[derive(Debug)]
+pub struct RunQueueState {
+    max_concurrent: usize,
+    max_queued: usize,
+    running: usize,
+    waiting: VecDeque<String>,
+}
+
+#[derive(Debug)]
+pub struct RunQueue {
+    state: Mutex<RunQueueState>,
+    notify: Notify,
+}
+
+#[derive(Debug)]
+pub struct RunTicket {
+    id: String,
+    admitted: bool,
+}
+
+#[derive(Debug)]
+pub struct RunPermit {
+    id: String,
+}
+
+impl RunQueue {
+    pub const fn new() -> Self {
+        RunQueue {
+            state: Mutex::new(RunQueueState {
+                max_concurrent: 1,
+                max_queued: 0,
+                running: 0,
+                waiting: VecDeque::new(),
+            }),
+            notify: Notify::const_new(),
+        }
+    }
+
+    fn lock(&self) -> MutexGuard<'_, RunQueueState> {
+        // the state is never left inconsistent while locked, so recover from poisoning
+        self.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+pub fn configure_run_queue(max_concurrent: usize, max_queued: usize) {
+    let mut state = RUN_QUEUE.lock();
+    state.max_concurrent = max_concurrent.max(1);
+    state.max_queued = max_queued;
+    drop(state);
+    RUN_QUEUE.notify.notify_waiters();
+}
+
+pub fn enqueue_run(id: &str) -> Result<RunTicket, Box<dyn std::error::Error>> {
+    let mut state = RUN_QUEUE.lock();
+    // runs that can be admitted straight away do not count against max_queued
+    if state.running + state.waiting.len() >= state.max_concurrent + state.max_queued {
+        return Err(Box::from(format!(
+            "[enqueue_run] run queue full ({} running, {} waiting)",
+            state.running,
+            state.waiting.len()
+        )));
+    }
+    state.waiting.push_back(id.to_string());
+    Ok(RunTicket {
+        id: id.to_string(),
+        admitted: false,
+    })
+}
+
+// 1 based position in the queue, None if the run is not waiting
+pub fn queue_position(id: &str) -> Option<usize> {
+    let state = RUN_QUEUE.lock();
+    state.waiting.iter().position(|x| x == id).map(|x| x + 1)
+}
+
+impl RunTicket {
+    pub async fn admit(mut self) -> RunPermit {
+        loop {
+            let notified = RUN_QUEUE.notify.notified();
+            tokio::pin!(notified);
+            // register interest before checking so a release is never missed
+            notified.as_mut().enable();
+            {
+                let mut state = RUN_QUEUE.lock();
+                let at_head = state.waiting.front() == Some(&self.id);
+                if at_head && state.running < state.max_concurrent {
+                    state.waiting.pop_front();
+                    state.running += 1;
+                    self.admitted = true;
+                    return RunPermit {
+                        id: self.id.clone(),
+                    };
+                }
+            }
+            notified.await;
+        }
+    }
+}
+
+impl Drop for RunTicket {
+    fn drop(&mut self) {
+        if !self.admitted {
+            let mut state = RUN_QUEUE.lock();
+            state.waiting.retain(|x| x != &self.id);
+            drop(state);
+            RUN_QUEUE.notify.notify_waiters();
+        }
+    }
+}
+
+impl RunPermit {
+    pub fn id(&self) -> &str {
+        &self.id
+    }
+}
+
+impl Drop for RunPermit {
+    fn drop(&mut self) {
+        let mut state = RUN_QUEUE.lock();
+        state.running = state.running.saturating_sub(1);
+        drop(state);
+        RUN_QUEUE.notify.notify_waiters();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // the queue is a process wide static, so all the assertions live in one test
+    #[tokio::test]
+    async fn test_run_queue_fifo_admission() {
+        configure_run_queue(1, 2);
+        let first = enqueue_run("run-a").unwrap();
+        let second = enqueue_run("run-b").unwrap();
+        let third = enqueue_run("run-c").unwrap();
+        assert!(enqueue_run("run-d").is_err());
+        assert_eq!(queue_position("run-b"), Some(2));
+
+        let permit = first.admit().await;
+        assert_eq!(permit.id(), "run-a");
+        assert_eq!(queue_position("run-b"), Some(1));
+
+        // dropping a waiting ticket frees its place
+        drop(second);
+        assert_eq!(queue_position("run-c"), Some(1));
+
+        let handle = tokio::spawn(async move { third.admit().await.id().to_string() });
+        tokio::task::yield_now().await;
+        assert!(!handle.is_finished());
+        drop(permit);
+        assert_eq!(handle.await.unwrap(), "run-c");
+        assert_eq!(RUN_QUEUE.lock().running, 0);
+    }
+}
@@ -0,0 +1,151 @@
+use crate::api::schema::{RankingParse, RankingParseStatus};
+use regex::Regex;
+use std::collections::BTreeSet;
+
+// strict parser for the FINAL RANKING section of a judge's response
+//
+// only the text after the last "FINAL RANKING:" marker is read, the section must
+// be a numbered list (1., 2., ...) of known labels, each label at most once,
+// parsing stops at the first line that is not part of the list
+
+pub const FINAL_RANKING_MARKER: &str = "FINAL RANKING:";
+
+pub fn parse_final_ranking(text: &str, labels: &[String]) -> RankingParse {
+    let section = match text.rfind(FINAL_RANKING_MARKER) {
+        Some(index) => &text[index + FINAL_RANKING_MARKER.len()..],
+        None => return invalid(format!("missing {} section", FINAL_RANKING_MARKER)),
+    };
+    // allow markdown emphasis around the entries (e.g. "1. **Response A**")
+    let re = Regex::new(r"^(\d+)\.\s*\**\s*(Response [A-Z]+)\s*\**\s*$").expect("valid regex");
+    let mut ranking: Vec<String> = vec![];
+    let mut seen: BTreeSet<String> = BTreeSet::new();
+    for line in section.lines() {
+        let line = line.trim();
+        if line.is_empty() {
+            continue;
+        }
+        let captures = match re.captures(line) {
+            Some(captures) => captures,
+            // the list has ended (or never started)
+            None => break,
+        };
+        let position: usize = captures[1].parse().unwrap_or(0);
+        let label = captures[2].to_string();
+        if position != ranking.len() + 1 {
+            return invalid(format!(
+                "expected position {} found {} for {}",
+                ranking.len() + 1,
+                position,
+                label
+            ));
+        }
+        if !labels.contains(&label) {
+            return invalid(format!("unknown label {}", label));
+        }
+        if !seen.insert(label.clone()) {
+            return invalid(format!("duplicate label {}", label));
+        }
+        ranking.push(label);
+    }
+    if ranking.is_empty() {
+        return invalid(format!("no ranked labels after {}", FINAL_RANKING_MARKER));
+    }
+    let missing: Vec<String> = labels
+        .iter()
+        .filter(|x| !seen.contains(*x))
+        .cloned()
+        .collect();
+    if missing.is_empty() {
+        RankingParse {
+            status: RankingParseStatus::Valid,
+            ranking,
+            reason: None,
+        }
+    } else {
+        RankingParse {
+            status: RankingParseStatus::Partial,
+            ranking,
+            reason: Some(format!("missing labels {}", missing.join(", "))),
+        }
+    }
+}
+
+fn invalid(reason: String) -> RankingParse {
+    RankingParse {
+        status: RankingParseStatus::Invalid,
+        ranking: vec![],
+        reason: Some(reason),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn labels() -> Vec<String> {
+        vec![
+            "Response A".to_string(),
+            "Response B".to_string(),
+            "Response C".to_string(),
+        ]
+    }
+
+    #[test]
+    fn test_parse_final_ranking_valid() {
+        let result = r#"
+        Response A provides good detail on X but misses Y...
+        1. Response B is mentioned in the prose and must be ignored
+        Response C offers the most comprehensive answer...
+
+        FINAL RANKING:
+        1. Response C
+        2. **Response A**
+        3. Response B
+
+        "#;
+        let parse = parse_final_ranking(result, &labels());
+        assert_eq!(parse.status, RankingParseStatus::Valid);
+        assert_eq!(
+            parse.ranking,
+            vec!["Response C", "Response A", "Response B"]
+        );
+    }
+
+    #[test]
+    fn test_parse_final_ranking_uses_last_marker() {
+        let result = "FINAL RANKING:\n1. Response A\n\nrevised\nFINAL RANKING:\n1. Response B\n2. Response A\n3. Response C";
+        let parse = parse_final_ranking(result, &labels());
+        assert_eq!(
+            parse.ranking,
+            vec!["Response B", "Response A", "Response C"]
+        );
+    }
+
+    #[test]
+    fn test_parse_final_ranking_missing_final() {
+        let result = "1. Response C\n2. Response A\n3. Response B";
+        let parse = parse_final_ranking(result, &labels());
+        assert_eq!(parse.status, RankingParseStatus::Invalid);
+    }
+
+    #[test]
+    fn test_parse_final_ranking_rejects_duplicates_and_unknown() {
+        let duplicate = "FINAL RANKING:\n1. Response C\n2. Response C\n3. Response B";
+        let parse = parse_final_ranking(duplicate, &labels());
+        assert_eq!(parse.status, RankingParseStatus::Invalid);
+        assert_eq!(parse.reason, Some("duplicate label Response C".to_string()));
+
+        let unknown = "FINAL RANKING:\n1. Response D\n2. Response C";
+        let parse = parse_final_ranking(unknown, &labels());
+        assert_eq!(parse.status, RankingParseStatus::Invalid);
+    }
+
+    #[test]
+    fn test_parse_final_ranking_partial() {
+        let result = "FINAL RANKING:\n1. Response B\n2. Response A\n\nThat concludes my evaluation.\n3. Response C";
+        let parse = parse_final_ranking(result, &labels());
+        assert_eq!(parse.status, RankingParseStatus::Partial);
+        assert_eq!(parse.ranking, vec!["Response B", "Response A"]);
+        assert_eq!(parse.reason, Some("missing labels Response C".to_string()));
+    }
+}
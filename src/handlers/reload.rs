@@ -0,0 +1,261 @@
+use crate::config::load::{ConfigInterface, ImplConfigInterface, Parameters};
+use crate::config::validate::validate_parameters;
+use crate::handlers::api_calls::build_http_client;
+use crate::handlers::documents::{DocumentStore, build_document_store};
+use crate::handlers::pipeline::validate_pipeline;
+use crate::handlers::prompts::{
+    PromptTemplates, load_prompt_templates, validate_trust_placeholder,
+};
+use crate::handlers::queue::configure_run_queue;
+use crate::{CONFIG_FILE, DOCUMENT_STORE, HTTP_CLIENT, MAP_LOOKUP, PROMPTS};
+use custom_logger as log;
+use std::path::Path;
+use std::sync::Arc;
+
+// configuration reload
+//
+// the config file is re-read on POST /v1/admin/reload and on SIGHUP, the new
+// configuration is validated (parsed, pipelines checked, prompt templates loaded and
+// the http client built) before it replaces the current one, a failed reload leaves
+// the current configuration unchanged
+//
+// a run takes a snapshot of the configuration when it is accepted and keeps using it
+// until it completes, so only the runs accepted after a reload use the new configuration
+//
+// the server port and the log level are only read at startup
+
+#[derive(Clone)]
+pub struct RunConfig {
+    pub parameters: Arc<Parameters>,
+    pub prompts: Arc<PromptTemplates>,
+    pub document_store: Arc<dyn DocumentStore>,
+    pub http_client: reqwest::Client,
+}
+
+tokio::task_local! {
+    // the configuration snapshot of the run executing in the task
+    static RUN_CONFIG: RunConfig;
+}
+
+// builds (and validates) the configuration, the prompt template files are relative
+// to the config directory
+pub fn build_run_config(
+    parameters: Parameters,
+    config_dir: &Path,
+) -> Result<RunConfig, Box<dyn std::error::Error>> {
+    for (name, stages) in parameters.pipelines.iter() {
+        validate_pipeline(stages)
+            .map_err(|e| format!("[build_run_config] pipeline {} {}", name, e))?;
+    }
+    let prompts = load_prompt_templates(&parameters.prompts, config_dir)?;
+    validate_trust_placeholder(&prompts, &parameters.council_members)?;
+    let http_client = build_http_client(&parameters.http)?;
+    let document_store = build_document_store(&parameters);
+    Ok(RunConfig {
+        parameters: Arc::new(parameters),
+        prompts: Arc::new(prompts),
+        document_store,
+        http_client,
+    })
+}
+
+// replaces the configuration used by new runs and resizes the run queue
+pub fn install_run_config(config: RunConfig) -> Result<(), Box<dyn std::error::Error>> {
+    configure_run_queue(
+        config.parameters.max_concurrent_runs,
+        config.parameters.max_queued_runs,
+    );
+    swap_run_config(config)
+}
+
+// replaces the configuration snapshot taken by new runs (without the run queue)
+fn swap_run_config(config: RunConfig) -> Result<(), Box<dyn std::error::Error>> {
+    // the locks are taken in the same order as in current_run_config, so a new run
+    // never sees a partially installed configuration
+    let mut params_guard = MAP_LOOKUP.lock().map_err(|_| "mutex lock failed")?;
+    let mut prompts_guard = PROMPTS.lock().map_err(|_| "mutex lock failed")?;
+    let mut store_guard = DOCUMENT_STORE.lock().map_err(|_| "mutex lock failed")?;
+    let mut client_guard = HTTP_CLIENT.lock().map_err(|_| "mutex lock failed")?;
+    *params_guard = Some(config.parameters);
+    *prompts_guard = Some(config.prompts);
+    *store_guard = Some(config.document_store);
+    *client_guard = Some(config.http_client);
+    Ok(())
+}
+
+// applies a change to the installed parameters (e.g. the admin api), the change is
+// validated before it is installed, runs already accepted keep their snapshot
+pub fn update_parameters<F>(change: F) -> Result<Arc<Parameters>, Box<dyn std::error::Error>>
+where
+    F: FnOnce(&mut Parameters) -> Result<(), Box<dyn std::error::Error>>,
+{
+    // the lock is held for the whole change so concurrent updates are not lost
+    let mut params_guard = MAP_LOOKUP.lock().map_err(|_| "mutex lock failed")?;
+    let mut parameters = params_guard
+        .as_ref()
+        .map(|x| x.as_ref().clone())
+        .ok_or("[update_parameters] configuration not initialised")?;
+    change(&mut parameters)?;
+    validate_parameters(&parameters)
+        .map_err(|e| format!("[update_parameters] invalid request {}", e))?;
+    // the installed templates must render the trust of a changed council
+    if let Some(prompts) = run_prompts()? {
+        validate_trust_placeholder(&prompts, &parameters.council_members)
+            .map_err(|e| format!("[update_parameters] invalid request {}", e))?;
+    }
+    let parameters = Arc::new(parameters);
+    *params_guard = Some(parameters.clone());
+    Ok(parameters)
+}
+
+// the snapshot of the current run, outside of a run the installed configuration
+pub fn current_run_config() -> Result<RunConfig, Box<dyn std::error::Error>> {
+    if let Ok(config) = RUN_CONFIG.try_with(|x| x.clone()) {
+        return Ok(config);
+    }
+    let params_guard = MAP_LOOKUP.lock().map_err(|_| "mutex lock failed")?;
+    let prompts_guard = PROMPTS.lock().map_err(|_| "mutex lock failed")?;
+    let store_guard = DOCUMENT_STORE.lock().map_err(|_| "mutex lock failed")?;
+    let client_guard = HTTP_CLIENT.lock().map_err(|_| "mutex lock failed")?;
+    match (
+        params_guard.as_ref(),
+        prompts_guard.as_ref(),
+        store_guard.as_ref(),
+        client_guard.as_ref(),
+    ) {
+        (Some(parameters), Some(prompts), Some(document_store), Some(http_client)) => {
+            Ok(RunConfig {
+                parameters: parameters.clone(),
+                prompts: prompts.clone(),
+                document_store: document_store.clone(),
+                http_client: http_client.clone(),
+            })
+        }
+        _ => Err(Box::from(
+            "[current_run_config] configuration not initialised",
+        )),
+    }
+}
+
+// executes the future (and everything it awaits) with the configuration snapshot
+pub async fn with_run_config<F: Future>(config: RunConfig, fut: F) -> F::Output {
+    RUN_CONFIG.scope(config, fut).await
+}
+
+pub fn run_parameters() -> Result<Option<Arc<Parameters>>, Box<dyn std::error::Error>> {
+    if let Ok(parameters) = RUN_CONFIG.try_with(|x| x.parameters.clone()) {
+        return Ok(Some(parameters));
+    }
+    let params_guard = MAP_LOOKUP.lock().map_err(|_| "mutex lock failed")?;
+    Ok(params_guard.clone())
+}
+
+pub fn run_prompts() -> Result<Option<Arc<PromptTemplates>>, Box<dyn std::error::Error>> {
+    if let Ok(prompts) = RUN_CONFIG.try_with(|x| x.prompts.clone()) {
+        return Ok(Some(prompts));
+    }
+    let prompts_guard = PROMPTS.lock().map_err(|_| "mutex lock failed")?;
+    Ok(prompts_guard.clone())
+}
+
+pub fn run_document_store() -> Result<Option<Arc<dyn DocumentStore>>, Box<dyn std::error::Error>> {
+    if let Ok(store) = RUN_CONFIG.try_with(|x| x.document_store.clone()) {
+        return Ok(Some(store));
+    }
+    let store_guard = DOCUMENT_STORE.lock().map_err(|_| "mutex lock failed")?;
+    Ok(store_guard.clone())
+}
+
+pub fn run_http_client() -> Result<Option<reqwest::Client>, Box<dyn std::error::Error>> {
+    if let Ok(client) = RUN_CONFIG.try_with(|x| x.http_client.clone()) {
+        return Ok(Some(client));
+    }
+    let client_guard = HTTP_CLIENT.lock().map_err(|_| "mutex lock failed")?;
+    Ok(client_guard.clone())
+}
+
+// re-reads the config file and installs it, returns the installed parameters
+pub fn reload_config() -> Result<Arc<Parameters>, Box<dyn std::error::Error>> {
+    let config_file = CONFIG_FILE
+        .lock()
+        .map_err(|_| "mutex lock failed")?
+        .clone()
+        .ok_or("[reload_config] config file not set")?;
+    let impl_config = ImplConfigInterface {};
+    let parameters = impl_config
+        .read(config_file.clone())
+        .map_err(|e| format!("[reload_config] reading {} {}", config_file, e))?;
+    let config_dir = Path::new(&config_file).parent().unwrap_or(Path::new("."));
+    let config = build_run_config(parameters, config_dir)?;
+    let parameters = config.parameters.clone();
+    install_run_config(config)?;
+    log::info!(
+        "[reload_config] reloaded {} ({} council members, prompt templates version {})",
+        config_file,
+        parameters.council_members.len(),
+        parameters.prompts.version
+    );
+    Ok(parameters)
+}
+
+// reloads the config on every SIGHUP
+#[cfg(unix)]
+pub async fn reload_on_hangup() {
+    use tokio::signal::unix::{SignalKind, signal};
+    let mut hangup = match signal(SignalKind::hangup()) {
+        Ok(hangup) => hangup,
+        Err(e) => {
+            log::error!("[reload_on_hangup] installing signal handler {}", e);
+            return;
+        }
+    };
+    while hangup.recv().await.is_some() {
+        log::info!("[reload_on_hangup] received SIGHUP");
+        if let Err(e) = reload_config() {
+            log::error!("[reload_on_hangup] configuration unchanged {}", e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::handlers::helper::get_council_chairman;
+    use crate::test_support::testing_parameters;
+
+    fn run_config(chairman: &str) -> RunConfig {
+        let mut parameters = testing_parameters();
+        parameters.council_chairman.name = chairman.to_string();
+        crate::test_support::run_config(parameters)
+    }
+
+    #[tokio::test]
+    async fn test_run_keeps_its_snapshot() {
+        // the run queue is process wide (and sized by other tests), so only the
+        // configuration is swapped
+        swap_run_config(run_config("before")).unwrap();
+        let snapshot = current_run_config().unwrap();
+        with_run_config(snapshot, async {
+            assert_eq!(get_council_chairman().unwrap().name, "before");
+            // a reload while the run is in flight
+            swap_run_config(run_config("after")).unwrap();
+            update_parameters(|params| {
+                params.history_window = 1;
+                Ok(())
+            })
+            .unwrap();
+            // the run keeps reading its snapshot
+            assert_eq!(get_council_chairman().unwrap().name, "before");
+            let parameters = current_run_config().unwrap().parameters;
+            assert_eq!(parameters.council_chairman.name, "before");
+            assert_ne!(parameters.history_window, 1);
+            // a run accepted now (a task without the snapshot) sees the new config
+            let fresh = tokio::spawn(async { current_run_config().unwrap().parameters })
+                .await
+                .unwrap();
+            assert_eq!(fresh.council_chairman.name, "after");
+            assert_eq!(fresh.history_window, 1);
+        })
+        .await;
+    }
+}
@@ -0,0 +1,313 @@
+use crate::api::schema::{ProgressEvent, ResponseSummary, RunState, RunStatus};
+use crate::handlers::queue::queue_position;
+use crate::{RUN_SUBSCRIBERS, RUNS};
+use custom_logger as log;
+use futures::channel::mpsc::{UnboundedReceiver, unbounded};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+// run registry - tracks the progress of each council run so that
+// callers can poll for the status instead of holding the connection open
+//
+// every update is also published as a progress event to the subscribers
+// of the run (server-sent events), the subscriber channels are closed
+// once the run completes or fails
+
+// finished runs are pruned (oldest first) once this limit is reached, when all the
+// retained runs are still queued or running new runs are rejected
+const MAX_RETAINED_RUNS: usize = 256;
+
+static RUN_COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+pub fn next_run_id() -> Result<String, Box<dyn std::error::Error>> {
+    let millis = SystemTime::now().duration_since(UNIX_EPOCH)?.as_millis();
+    let count = RUN_COUNTER.fetch_add(1, Ordering::SeqCst);
+    Ok(format!("run-{:x}-{:04}", millis, count))
+}
+
+pub fn create_run(
+    id: &str,
+    title: String,
+    members: Vec<String>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let status = RunStatus {
+        id: id.to_string(),
+        title,
+        state: RunState::Queued,
+        stage: "queued".to_string(),
+        queue_position: None,
+        members: members
+            .into_iter()
+            .map(|name| (name, "pending".to_string()))
+            .collect(),
+        summary: None,
+        error: None,
+    };
+    let mut runs = RUNS.lock().map_err(|_| "mutex lock failed")?;
+    if !prune_runs(&mut runs, MAX_RETAINED_RUNS) {
+        return Err(Box::from(format!(
+            "[create_run] run queue full {} runs are queued or running",
+            runs.len()
+        )));
+    }
+    runs.insert(id.to_string(), status);
+    Ok(())
+}
+
+pub fn get_run(id: &str) -> Result<Option<RunStatus>, Box<dyn std::error::Error>> {
+    let runs = RUNS.lock().map_err(|_| "mutex lock failed")?;
+    let mut run = runs.get(id).cloned();
+    drop(runs);
+    // the position is live, so always read it from the queue
+    if let Some(status) = run.as_mut() {
+        status.queue_position = queue_position(id);
+    }
+    Ok(run)
+}
+
+pub fn set_run_stage(id: &str, stage: &str) -> Result<(), Box<dyn std::error::Error>> {
+    update_run(id, |run| {
+        run.state = RunState::Running;
+        run.stage = stage.to_string();
+    })?;
+    publish_event(ProgressEvent::new("stage", id).with_data(serde_json::json!(stage)));
+    Ok(())
+}
+
+pub fn set_member_status(
+    id: &str,
+    stage: &str,
+    member: &str,
+    status: String,
+) -> Result<(), Box<dyn std::error::Error>> {
+    update_run(id, |run| {
+        run.members
+            .insert(member.to_string(), format!("{}:{}", stage, status));
+    })?;
+    publish_event(ProgressEvent::new(stage, id).with_member(member, status));
+    Ok(())
+}
+
+pub fn complete_run(id: &str, summary: ResponseSummary) -> Result<(), Box<dyn std::error::Error>> {
+    let data = serde_json::to_value(&summary)?;
+    update_run(id, |run| {
+        run.state = RunState::Completed;
+        run.stage = "completed".to_string();
+        run.summary = Some(summary);
+    })?;
+    publish_event(ProgressEvent::new("summary", id).with_data(data));
+    close_subscribers(id);
+    Ok(())
+}
+
+pub fn fail_run(id: &str, error: String) -> Result<(), Box<dyn std::error::Error>> {
+    let data = serde_json::json!(error);
+    update_run(id, |run| {
+        run.state = RunState::Failed;
+        run.error = Some(error);
+    })?;
+    publish_event(ProgressEvent::new("error", id).with_data(data));
+    close_subscribers(id);
+    Ok(())
+}
+
+// marks the run failed when it is dropped before the run finished, e.g. the flow of
+// a synchronous request is dropped when the client disconnects (also while queued)
+// or the task executing the flow panics, so no run stays queued or running
+pub struct RunGuard {
+    id: String,
+    finished: bool,
+}
+
+impl RunGuard {
+    pub fn new(id: &str) -> Self {
+        RunGuard {
+            id: id.to_string(),
+            finished: false,
+        }
+    }
+
+    pub fn finish(mut self) {
+        self.finished = true;
+    }
+}
+
+impl Drop for RunGuard {
+    fn drop(&mut self) {
+        if !self.finished {
+            log::error!("[RunGuard] run {} cancelled before it finished", self.id);
+            let _ = fail_run(
+                &self.id,
+                "cancelled before the run finished (client disconnected or flow aborted)"
+                    .to_string(),
+            );
+        }
+    }
+}
+
+// returns a channel receiving all further progress events of the run,
+// for a run that has already finished the final event is sent straight away
+pub fn subscribe_run(
+    id: &str,
+) -> Result<Option<UnboundedReceiver<ProgressEvent>>, Box<dyn std::error::Error>> {
+    let (tx, rx) = unbounded();
+    // the state is checked and the subscriber registered while holding the registry
+    // lock, a run finishing concurrently updates its state (under the same lock)
+    // before it closes the subscribers, so the subscriber is either closed or gets
+    // the final event straight away
+    let runs = RUNS.lock().map_err(|_| "mutex lock failed")?;
+    let status = match runs.get(id) {
+        Some(status) => status,
+        None => return Ok(None),
+    };
+    match status.state {
+        RunState::Completed => {
+            let data = serde_json::to_value(&status.summary)?;
+            let _ = tx.unbounded_send(ProgressEvent::new("summary", id).with_data(data));
+        }
+        RunState::Failed => {
+            let data = serde_json::json!(status.error);
+            let _ = tx.unbounded_send(ProgressEvent::new("error", id).with_data(data));
+        }
+        _ => {
+            let mut subscribers = RUN_SUBSCRIBERS.lock().map_err(|_| "mutex lock failed")?;
+            subscribers.entry(id.to_string()).or_default().push(tx);
+        }
+    }
+    drop(runs);
+    Ok(Some(rx))
+}
+
+pub fn publish_event(event: ProgressEvent) {
+    if let Ok(mut subscribers) = RUN_SUBSCRIBERS.lock()
+        && let Some(senders) = subscribers.get_mut(&event.run_id)
+    {
+        // drop the subscribers that have disconnected
+        senders.retain(|tx| tx.unbounded_send(event.clone()).is_ok());
+    }
+}
+
+fn close_subscribers(id: &str) {
+    if let Ok(mut subscribers) = RUN_SUBSCRIBERS.lock() {
+        subscribers.remove(id);
+    }
+}
+
+fn update_run<F>(id: &str, f: F) -> Result<(), Box<dyn std::error::Error>>
+where
+    F: FnOnce(&mut RunStatus),
+{
+    let mut runs = RUNS.lock().map_err(|_| "mutex lock failed")?;
+    match runs.get_mut(id) {
+        Some(run) => {
+            f(run);
+            Ok(())
+        }
+        None => Err(Box::from(format!("[update_run] run {} not found", id))),
+    }
+}
+
+// removes finished runs (oldest first) until there is room for a new run, returns
+// false when the registry is full of queued or running runs
+fn prune_runs(runs: &mut std::collections::BTreeMap<String, RunStatus>, limit: usize) -> bool {
+    while runs.len() >= limit {
+        // run ids are time ordered so the first finished entry is the oldest
+        let oldest = runs
+            .iter()
+            .find(|(_, run)| matches!(run.state, RunState::Completed | RunState::Failed))
+            .map(|(id, _)| id.clone());
+        match oldest {
+            Some(id) => {
+                runs.remove(&id);
+            }
+            None => return false,
+        }
+    }
+    true
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::StreamExt;
+
+    #[tokio::test]
+    async fn test_subscribe_finished_run() {
+        let id = next_run_id().unwrap();
+        create_run(&id, "finished".to_string(), vec![]).unwrap();
+        let rx = subscribe_run(&id).unwrap().unwrap();
+        complete_run(&id, ResponseSummary::default()).unwrap();
+        let events: Vec<ProgressEvent> = rx.collect().await;
+        assert_eq!(events.last().unwrap().event, "summary");
+
+        // a subscriber arriving after the run finished gets the final event and the
+        // stream ends
+        let rx = subscribe_run(&id).unwrap().unwrap();
+        let events: Vec<ProgressEvent> = rx.collect().await;
+        assert_eq!(events.len(), 1);
+
+        let id = next_run_id().unwrap();
+        create_run(&id, "failed".to_string(), vec![]).unwrap();
+        fail_run(&id, "member timeout".to_string()).unwrap();
+        let rx = subscribe_run(&id).unwrap().unwrap();
+        let events: Vec<ProgressEvent> = rx.collect().await;
+        assert_eq!(events.len(), 1);
+        assert_eq!(events[0].event, "error");
+        assert!(!RUN_SUBSCRIBERS.lock().unwrap().contains_key(&id));
+    }
+
+    #[test]
+    fn test_dropped_run_is_failed() {
+        let id = next_run_id().unwrap();
+        create_run(&id, "dropped".to_string(), vec![]).unwrap();
+        drop(RunGuard::new(&id));
+        let status = get_run(&id).unwrap().unwrap();
+        assert_eq!(status.state, RunState::Failed);
+        assert!(status.error.unwrap().contains("cancelled"));
+
+        let id = next_run_id().unwrap();
+        create_run(&id, "finished".to_string(), vec![]).unwrap();
+        let guard = RunGuard::new(&id);
+        complete_run(&id, ResponseSummary::default()).unwrap();
+        guard.finish();
+        assert_eq!(get_run(&id).unwrap().unwrap().state, RunState::Completed);
+    }
+
+    #[test]
+    fn test_prune_runs() {
+        let run = |id: &str, state: RunState| {
+            (
+                id.to_string(),
+                RunStatus {
+                    id: id.to_string(),
+                    state,
+                    ..Default::default()
+                },
+            )
+        };
+        let mut runs = std::collections::BTreeMap::from([
+            run("run-1", RunState::Running),
+            run("run-2", RunState::Completed),
+            run("run-3", RunState::Queued),
+            run("run-4", RunState::Failed),
+            run("run-5", RunState::Completed),
+        ]);
+        // the oldest finished runs go first, active runs are kept
+        assert!(prune_runs(&mut runs, 5));
+        assert_eq!(
+            runs.keys().cloned().collect::<Vec<String>>(),
+            vec!["run-1", "run-3", "run-4", "run-5"]
+        );
+        assert!(prune_runs(&mut runs, 4));
+        assert_eq!(
+            runs.keys().cloned().collect::<Vec<String>>(),
+            vec!["run-1", "run-3", "run-5"]
+        );
+        // the cap holds when only active runs are left
+        assert!(!prune_runs(&mut runs, 2));
+        assert_eq!(
+            runs.keys().cloned().collect::<Vec<String>>(),
+            vec!["run-1", "run-3"]
+        );
+    }
+}
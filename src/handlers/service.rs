@@ -1,32 +1,127 @@
-use crate::handlers::controller::flow_control;
-use crate::handlers::helper::set_semaphore;
+use crate::api::schema::{
+    ChatCompletionRequest, LLMCouncilRequestSchema, ProgressEvent, RunCreated,
+};
+use crate::handlers::admin::{
+    COUNCIL_PREFIX, RELOAD_PATH, authorize_admin, council_admin, is_admin_route,
+};
+use crate::handlers::api_calls::get_document;
+use crate::handlers::breaker::breaker_states;
+use crate::handlers::controller::run_flow;
+use crate::handlers::helper::get_council_members;
+use crate::handlers::openai::*;
+use crate::handlers::pipeline::resolve_pipeline;
+use crate::handlers::queue::{enqueue_run, queue_position};
+use crate::handlers::reload::{current_run_config, reload_config, with_run_config};
+use crate::handlers::runs::*;
 use custom_logger as log;
+use futures::StreamExt;
+use futures::stream::BoxStream;
 use http::{Method, Request, Response, StatusCode};
 use http_body_util::BodyExt;
-use http_body_util::Full;
-use hyper::body::{Bytes, Incoming};
+use http_body_util::combinators::UnsyncBoxBody;
+use http_body_util::{Full, StreamBody};
+use hyper::body::{Bytes, Frame, Incoming};
+use std::convert::Infallible;
 
-pub async fn endpoints(req: Request<Incoming>) -> Result<Response<Full<Bytes>>, hyper::Error> {
+pub type ServiceBody = UnsyncBoxBody<Bytes, Infallible>;
+
+// result of starting a run, streamed runs hand back the formatted event stream
+enum RunStarted {
+    Contents(String),
+    Stream(BoxStream<'static, String>),
+}
+
+pub async fn endpoints(req: Request<Incoming>) -> Result<Response<ServiceBody>, hyper::Error> {
     let mut response = Response::new(Full::default());
-    let request = req.uri().path();
+    let request = req.uri().path().to_string();
     log::debug!("{}", request);
+    if is_admin_route(&request) {
+        let authorization = req
+            .headers()
+            .get(http::header::AUTHORIZATION)
+            .and_then(|x| x.to_str().ok());
+        if let Err(err) = authorize_admin(authorization) {
+            log::error!("[endpoints] {} {}", request, err);
+            let status = if err.to_string().contains("unauthorized") {
+                StatusCode::UNAUTHORIZED
+            } else {
+                StatusCode::FORBIDDEN
+            };
+            *response.status_mut() = status;
+            *response.body_mut() = Full::from(format!("{}\n", err));
+            return Ok(response.map(|body| body.boxed_unsync()));
+        }
+    }
+    if request.starts_with(COUNCIL_PREFIX) {
+        let method = req.method().clone();
+        let query = req.uri().query().map(|x| x.to_string());
+        let data = req.into_body().collect().await?.to_bytes();
+        match council_admin(&method, &request, query.as_deref(), data).await {
+            Ok(contents) => *response.body_mut() = Full::from(contents),
+            Err(err) => {
+                let status = if err.to_string().contains("invalid request") {
+                    StatusCode::BAD_REQUEST
+                } else if err.to_string().contains("conflict") {
+                    StatusCode::CONFLICT
+                } else if err.to_string().contains("not found") {
+                    StatusCode::NOT_FOUND
+                } else {
+                    log::error!("[endpoints] {}", err);
+                    StatusCode::INTERNAL_SERVER_ERROR
+                };
+                *response.status_mut() = status;
+                *response.body_mut() = Full::from(format!("{}\n", err));
+            }
+        }
+        return Ok(response.map(|body| body.boxed_unsync()));
+    }
     match *req.method() {
-        Method::POST => match request {
-            x if x.contains("/v1/chat/completions") => {
+        Method::POST => match request.as_str() {
+            RELOAD_PATH => match reload_config() {
+                Ok(parameters) => {
+                    let members: Vec<String> = parameters
+                        .council_members
+                        .iter()
+                        .map(|x| x.name.clone())
+                        .collect();
+                    let content = serde_json::json!({
+                        "status": "reloaded",
+                        "councilMembers": members,
+                        "councilChairman": parameters.council_chairman.name,
+                        "promptVersion": parameters.prompts.version,
+                    });
+                    *response.body_mut() = Full::from(content.to_string());
+                }
+                Err(err) => {
+                    log::error!("[endpoints] {}", err);
+                    *response.status_mut() = StatusCode::UNPROCESSABLE_ENTITY;
+                    *response.body_mut() =
+                        Full::from(format!("{} - configuration unchanged\n", err));
+                }
+            },
+            x if x.contains("/v1/chat/completions") || x.contains("/v1/council/runs") => {
+                let force_async = x.contains("/v1/council/runs");
                 let data = req.into_body().collect().await?.to_bytes();
-                let result = flow_control("/v1/chat/completions".to_owned(), data).await;
+                let result = start_run(data, force_async).await;
                 match result {
-                    Ok(contents) => {
+                    Ok(RunStarted::Stream(frames)) => {
+                        return Ok(sse_response(frames));
+                    }
+                    Ok(RunStarted::Contents(contents)) => {
+                        if force_async {
+                            *response.status_mut() = StatusCode::ACCEPTED;
+                        }
                         *response.body_mut() = Full::from(contents);
                     }
                     Err(err) => {
-                        if err.to_string().contains("still processing") {
+                        if err.to_string().contains("invalid request") {
+                            *response.status_mut() = StatusCode::BAD_REQUEST;
+                            *response.body_mut() = Full::from(format!("{}\n", err));
+                        } else if err.to_string().contains("run queue full") {
                             *response.status_mut() = StatusCode::SERVICE_UNAVAILABLE;
-                            *response.body_mut() = Full::from(
-                                "[endpoints] still processing - try again later\n".to_string(),
-                            );
+                            *response.body_mut() =
+                                Full::from(format!("{} - try again later\n", err));
                         } else {
-                            let _ = set_semaphore(false);
                             log::error!("[endpoints] {}", err);
                             *response.status_mut() = StatusCode::INTERNAL_SERVER_ERROR;
                             *response.body_mut() = Full::from(format!("[endpoints] {}\n", err));
@@ -40,14 +135,55 @@ pub async fn endpoints(req: Request<Incoming>) -> Result<Response<Full<Bytes>>,
                 *response.status_mut() = StatusCode::NOT_FOUND;
             }
         },
-        Method::GET => match request {
+        Method::GET => match request.as_str() {
             x if x.contains("/v1/health") => {
-                let content = format!(
-                    r##"{{ "status":"ok", "appplication": "{}", "version": "{}" }}"##,
-                    env!("CARGO_PKG_NAME"),
-                    env!("CARGO_PKG_VERSION"),
-                );
-                *response.body_mut() = Full::from(content);
+                // the circuit breaker state of the members that have been called
+                let breakers = breaker_states().unwrap_or_default();
+                let content = serde_json::json!({
+                    "status": "ok",
+                    "appplication": env!("CARGO_PKG_NAME"),
+                    "version": env!("CARGO_PKG_VERSION"),
+                    "breakers": breakers,
+                });
+                *response.body_mut() = Full::from(content.to_string());
+            }
+            x if x.starts_with("/v1/council/runs/") && x.ends_with("/events") => {
+                let id = x
+                    .trim_start_matches("/v1/council/runs/")
+                    .trim_end_matches("/events");
+                match subscribe_run(id) {
+                    Ok(Some(rx)) => return Ok(sse_response(rx.map(|x| x.to_sse()).boxed())),
+                    Ok(None) => {
+                        *response.status_mut() = StatusCode::NOT_FOUND;
+                        *response.body_mut() =
+                            Full::from(format!("[endpoints] run {} not found\n", id));
+                    }
+                    Err(err) => {
+                        *response.status_mut() = StatusCode::INTERNAL_SERVER_ERROR;
+                        *response.body_mut() = Full::from(format!("[endpoints] {}\n", err));
+                    }
+                }
+            }
+            x if x.starts_with("/v1/council/runs/") => {
+                let id = x.trim_start_matches("/v1/council/runs/");
+                match get_run(id) {
+                    Ok(Some(status)) => match serde_json::to_string_pretty(&status) {
+                        Ok(json) => *response.body_mut() = Full::from(json),
+                        Err(err) => {
+                            *response.status_mut() = StatusCode::INTERNAL_SERVER_ERROR;
+                            *response.body_mut() = Full::from(format!("[endpoints] {}\n", err));
+                        }
+                    },
+                    Ok(None) => {
+                        *response.status_mut() = StatusCode::NOT_FOUND;
+                        *response.body_mut() =
+                            Full::from(format!("[endpoints] run {} not found\n", id));
+                    }
+                    Err(err) => {
+                        *response.status_mut() = StatusCode::INTERNAL_SERVER_ERROR;
+                        *response.body_mut() = Full::from(format!("[endpoints] {}\n", err));
+                    }
+                }
             }
             &_ => {}
         },
@@ -57,5 +193,135 @@ pub async fn endpoints(req: Request<Incoming>) -> Result<Response<Full<Bytes>>,
             *response.status_mut() = StatusCode::NOT_FOUND;
         }
     };
-    Ok(response)
+    Ok(response.map(|body| body.boxed_unsync()))
+}
+
+// streams the (already formatted) events of a run as server-sent events,
+// the stream ends once the run has completed or failed
+fn sse_response(frames: BoxStream<'static, String>) -> Response<ServiceBody> {
+    let stream = frames.map(|frame| Ok::<_, Infallible>(Frame::data(Bytes::from(frame))));
+    let mut response = Response::new(BodyExt::boxed_unsync(StreamBody::new(stream)));
+    let headers = response.headers_mut();
+    headers.insert(
+        http::header::CONTENT_TYPE,
+        http::HeaderValue::from_static("text/event-stream"),
+    );
+    headers.insert(
+        http::header::CACHE_CONTROL,
+        http::HeaderValue::from_static("no-cache"),
+    );
+    response
+}
+
+// registers and enqueues a new run, if the run is asynchronous the flow is spawned
+// and the run id is returned immediately, if the run is streamed the flow is spawned
+// and the progress events are returned, otherwise the flow is awaited
+// (including any time spent waiting in the queue) and the summary is returned
+//
+// both the council request schema and the openai chat completion format
+// (model "council") are accepted, the response follows the request format
+async fn start_run(
+    data: Bytes,
+    force_async: bool,
+) -> Result<RunStarted, Box<dyn std::error::Error>> {
+    // the run keeps the configuration installed when it is accepted, also after a reload
+    let config = current_run_config()?;
+    with_run_config(config, accept_run(data, force_async)).await
+}
+
+async fn accept_run(
+    data: Bytes,
+    force_async: bool,
+) -> Result<RunStarted, Box<dyn std::error::Error>> {
+    let value: serde_json::Value =
+        serde_json::from_slice(&data).map_err(|e| format!("[accept_run] invalid request {}", e))?;
+    let run_id = next_run_id()?;
+    let chat = is_chat_completion_request(&value);
+    let req: LLMCouncilRequestSchema = if chat {
+        let chat_req: ChatCompletionRequest = serde_json::from_value(value)
+            .map_err(|e| format!("[accept_run] invalid request {}", e))?;
+        to_council_request(chat_req, &run_id)?
+    } else {
+        serde_json::from_value(value).map_err(|e| format!("[accept_run] invalid request {}", e))?
+    };
+    // reject unknown pipelines and inconsistent stage modes before the run is queued
+    resolve_pipeline(&req)?;
+    let members = get_council_members()?
+        .iter()
+        .map(|x| x.name.clone())
+        .collect();
+    create_run(&run_id, req.title.clone(), members)?;
+    // fail fast when the queue is full rather than report a failed run to the caller
+    let ticket = match enqueue_run(&run_id) {
+        Ok(ticket) => ticket,
+        Err(err) => {
+            let _ = fail_run(&run_id, err.to_string());
+            return Err(err);
+        }
+    };
+    if req.stream {
+        // subscribe before spawning so that no events are missed
+        let rx = subscribe_run(&run_id)?.ok_or("[accept_run] run not registered")?;
+        publish_event(
+            ProgressEvent::new("queued", &run_id)
+                .with_data(serde_json::json!(queue_position(&run_id))),
+        );
+        log::info!("[accept_run] spawning streamed run {}", run_id);
+        let config = current_run_config()?;
+        tokio::task::spawn(with_run_config(config, async move {
+            let _ = run_flow("/v1/chat/completions".to_owned(), run_id, ticket, req).await;
+        }));
+        let frames = if chat {
+            rx.scan(String::new(), |content, event| {
+                futures::future::ready(Some(to_chat_stream_frame(content, event)))
+            })
+            .boxed()
+        } else {
+            rx.map(|event| event.to_sse()).boxed()
+        };
+        Ok(RunStarted::Stream(frames))
+    } else if force_async || req.run_async {
+        log::info!("[accept_run] spawning run {}", run_id);
+        let spawn_id = run_id.clone();
+        let config = current_run_config()?;
+        tokio::task::spawn(with_run_config(config, async move {
+            let _ = run_flow("/v1/chat/completions".to_owned(), spawn_id, ticket, req).await;
+        }));
+        let created = RunCreated {
+            id: run_id.clone(),
+            status_url: format!("/v1/council/runs/{}", run_id),
+            queue_position: queue_position(&run_id),
+        };
+        Ok(RunStarted::Contents(serde_json::to_string_pretty(
+            &created,
+        )?))
+    } else {
+        let title = req.title.clone();
+        let summary = run_flow(
+            "/v1/chat/completions".to_owned(),
+            run_id.clone(),
+            ticket,
+            req,
+        )
+        .await?;
+        if chat {
+            // the assistant message is empty when the pipeline skips the synthesis (no
+            // chairman is selected)
+            let content = match &summary.chairman {
+                Some(chairman) => {
+                    let contents = get_document(&chairman.name, "chairman-summary", &title).await?;
+                    extract_message_content(&contents)
+                }
+                None => String::new(),
+            };
+            let completion = to_chat_completion(&run_id, content, summary);
+            Ok(RunStarted::Contents(serde_json::to_string_pretty(
+                &completion,
+            )?))
+        } else {
+            Ok(RunStarted::Contents(serde_json::to_string_pretty(
+                &summary,
+            )?))
+        }
+    }
 }
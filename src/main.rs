@@ -1,37 +1,108 @@
+use crate::api::schema::{ProgressEvent, RunStatus};
 use crate::config::load::{ConfigInterface, ImplConfigInterface, Parameters};
+use crate::config::overrides::{CONFIG_PATH_VAR, masked_config};
+use crate::handlers::breaker::CircuitBreaker;
+use crate::handlers::conversations::Conversation;
+use crate::handlers::documents::DocumentStore;
+use crate::handlers::prompts::PromptTemplates;
+use crate::handlers::queue::RunQueue;
+use crate::handlers::reload::{build_run_config, install_run_config};
 use crate::handlers::service::endpoints;
 use custom_logger as log;
+use futures::channel::mpsc::UnboundedSender;
 use hyper::server::conn::http1;
 use hyper::service::service_fn;
 use hyper_util::rt::TokioIo;
 use mimalloc::MiMalloc;
+use std::collections::BTreeMap;
 use std::net::{Ipv4Addr, SocketAddr};
-use std::sync::Mutex;
+use std::path::Path;
+use std::sync::atomic::AtomicUsize;
+use std::sync::{Arc, Mutex};
 use tokio::net::TcpListener;
 
 mod api;
 mod config;
 mod handlers;
+#[cfg(test)]
+mod test_support;
 
 #[global_allocator]
 static GLOBAL: MiMalloc = MiMalloc;
 
-// used for lookup in read mode only
-static MAP_LOOKUP: Mutex<Option<Parameters>> = Mutex::new(None);
-static SEMAPHORE: Mutex<bool> = Mutex::new(false);
+// the config file, re-read on reload
+static CONFIG_FILE: Mutex<Option<String>> = Mutex::new(None);
+// current configuration, replaced as a whole on reload (runs use a snapshot)
+static MAP_LOOKUP: Mutex<Option<Arc<Parameters>>> = Mutex::new(None);
+// shared (pooled) http client for all outbound calls
+static HTTP_CLIENT: Mutex<Option<reqwest::Client>> = Mutex::new(None);
+// document persistence backend (selected in the config)
+static DOCUMENT_STORE: Mutex<Option<Arc<dyn DocumentStore>>> = Mutex::new(None);
+// ranking and chairman prompt templates
+static PROMPTS: Mutex<Option<Arc<PromptTemplates>>> = Mutex::new(None);
+// circuit breaker per council member, keyed by member name
+static BREAKERS: Mutex<BTreeMap<String, CircuitBreaker>> = Mutex::new(BTreeMap::new());
+// bounded FIFO queue limiting the number of concurrent council runs
+static RUN_QUEUE: RunQueue = RunQueue::new();
+// run status registry, keyed by run id
+static RUNS: Mutex<BTreeMap<String, RunStatus>> = Mutex::new(BTreeMap::new());
+// conversation history, keyed by conversation id
+static CONVERSATIONS: Mutex<BTreeMap<String, Conversation>> = Mutex::new(BTreeMap::new());
+// position of the next round-robin chairman
+static CHAIRMAN_ROTATION: AtomicUsize = AtomicUsize::new(0);
+// progress event subscribers (server-sent events), keyed by run id
+static RUN_SUBSCRIBERS: Mutex<BTreeMap<String, Vec<UnboundedSender<ProgressEvent>>>> =
+    Mutex::new(BTreeMap::new());
 
 fn main() {
     // read and parse config
-    // for unikernels it will allways be '/etc/config/application-config.json'
+    // the path is taken from --config <path>, the LLM_COUNCIL_CONFIG environment variable
+    // or defaults to '/etc/config/application-config.json' (allways used for unikernels)
     let impl_config = ImplConfigInterface {};
+
+    // --check-config <path> validates the config file and exits (deployment gate),
+    // the result is plain cli output on stdout/stderr as the logger is only set up
+    // from the log level of a valid config
+    let args: Vec<String> = std::env::args().collect();
+    if let Some(pos) = args.iter().position(|x| x == "--check-config") {
+        let Some(path) = args.get(pos + 1) else {
+            eprintln!("[main] usage --check-config <path>");
+            std::process::exit(2);
+        };
+        match check_config(&impl_config, path) {
+            Ok(_) => {
+                println!("[main] config {} is valid", path);
+                std::process::exit(0);
+            }
+            Err(e) => {
+                eprintln!("[main] config {} is invalid {}", path, e);
+                std::process::exit(1);
+            }
+        }
+    }
+
     #[cfg(feature = "local")]
-    let res_params = impl_config.read("config/application-config.json".to_string());
+    let default_config_file = "config/application-config.json";
 
     #[cfg(not(feature = "local"))]
-    let res_params = impl_config.read("/etc/config/application-config.json".to_string());
+    let default_config_file = "/etc/config/application-config.json";
 
-    let parameters = match res_params {
-        Ok(params) => params,
+    let config_file = match args.iter().position(|x| x == "--config") {
+        Some(pos) => match args.get(pos + 1) {
+            Some(path) => path.clone(),
+            None => {
+                eprintln!("[main] usage --config <path>");
+                std::process::exit(2);
+            }
+        },
+        None => std::env::var(CONFIG_PATH_VAR).unwrap_or(default_config_file.to_string()),
+    };
+    let config_file = config_file.as_str();
+
+    let res_params = impl_config.read_with_env(config_file.to_string(), std::env::vars());
+
+    let (parameters, overrides) = match res_params {
+        Ok(result) => result,
         Err(e) => {
             eprintln!("[main] error parsing config {:?}", e.to_string());
             std::process::exit(1);
@@ -54,11 +125,48 @@ fn main() {
     log::info!("application : {}", env!("CARGO_PKG_NAME"));
     log::info!("author      : {}", env!("CARGO_PKG_AUTHORS"));
     log::info!("version     : {}", env!("CARGO_PKG_VERSION"));
+    log::info!("config      : {}", config_file);
+
+    // the effective configuration, after the environment overrides
+    if !overrides.applied.is_empty() {
+        log::info!(
+            "[main] environment overrides {}",
+            overrides.applied.join(", ")
+        );
+    }
+    if !overrides.skipped.is_empty() {
+        log::info!(
+            "[main] environment overrides skipped (no matching setting) {}",
+            overrides.skipped.join(", ")
+        );
+    }
+    match masked_config(&parameters) {
+        Ok(config) => log::info!("[main] effective config {}", config),
+        Err(e) => log::error!("[main] error formatting config {}", e),
+    }
 
     // parameters used in service
-    *MAP_LOOKUP.lock().unwrap() = Some(parameters.clone());
+    *CONFIG_FILE.lock().unwrap() = Some(config_file.to_string());
+    let server_port = parameters.server_port;
+    // the prompt template files are relative to the config directory
+    let config_dir = Path::new(config_file).parent().unwrap_or(Path::new("."));
+    let run_config = match build_run_config(parameters, config_dir) {
+        Ok(run_config) => run_config,
+        Err(e) => {
+            log::error!("[main] error loading configuration {}", e);
+            std::process::exit(1);
+        }
+    };
+    log::info!(
+        "[main] prompt templates version {}",
+        run_config.prompts.version
+    );
+    if let Err(e) = install_run_config(run_config) {
+        log::error!("[main] error installing configuration {}", e);
+        std::process::exit(1);
+    }
 
-    let result = run_server(parameters.server_port);
+    let result = run_server(server_port);
     match result {
         Ok(_) => log::info!("[main] unikernel shutdown"),
         Err(err) => {
@@ -68,11 +176,24 @@ fn main() {
     }
 }
 
+// the same checks as at startup, including the pipelines and the prompt templates
+fn check_config(
+    impl_config: &ImplConfigInterface,
+    path: &str,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let parameters = impl_config.read(path.to_string())?;
+    let config_dir = Path::new(path).parent().unwrap_or(Path::new("."));
+    build_run_config(parameters, config_dir)?;
+    Ok(())
+}
+
 #[tokio::main]
 pub async fn run_server(port: usize) -> Result<(), Box<dyn std::error::Error>> {
     let addr = SocketAddr::new(Ipv4Addr::new(0, 0, 0, 0).into(), port as u16);
     log::info!("[run_server] starting to serve on http://{}", addr);
     let listener = TcpListener::bind(addr).await?;
+    #[cfg(unix)]
+    tokio::task::spawn(handlers::reload::reload_on_hangup());
     loop {
         let (stream, _) = listener.accept().await?;
         let io = TokioIo::new(stream);
@@ -0,0 +1,40 @@
+use crate::config::load::{ImplConfigInterface, ModelSchema, Parameters};
+use crate::handlers::documents::MemoryDocumentStore;
+use crate::handlers::prompts::PromptTemplates;
+use crate::handlers::reload::RunConfig;
+use std::sync::Arc;
+
+// fixtures shared by the unit tests
+
+// the testing config without the environment overrides of the process
+pub fn testing_parameters() -> Parameters {
+    let impl_config = ImplConfigInterface {};
+    impl_config
+        .read_with_env("config/application-config-testing.json".to_string(), vec![])
+        .unwrap()
+        .0
+}
+
+// a council member with the default settings, the url and model follow the id and name
+pub fn member(id: usize, name: &str) -> ModelSchema {
+    ModelSchema {
+        id,
+        name: name.to_string(),
+        url: format!("http://192.168.1.29:808{}", id),
+        model: format!("{}-model", name),
+        timeout_secs: None,
+        weight: None,
+        trust: None,
+        generation: Default::default(),
+    }
+}
+
+// a configuration snapshot with the built in defaults and an in memory document store
+pub fn run_config(parameters: Parameters) -> RunConfig {
+    RunConfig {
+        parameters: Arc::new(parameters),
+        prompts: Arc::new(PromptTemplates::default()),
+        document_store: Arc::new(MemoryDocumentStore::default()),
+        http_client: reqwest::Client::new(),
+    }
+}